@@ -0,0 +1,281 @@
+use super::database::session::{self, Session};
+use crate::{
+    app::{format_date, user},
+    git::{LockChange, Snapshot},
+};
+use chrono::{Datelike, Local, TimeZone};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashMap},
+    time::{Duration, SystemTime},
+};
+
+/// Time that someone had the server, from taking the lock to releasing it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostingPeriod {
+    /// Name of the host, see `user::display`.
+    pub host: String,
+    pub start: SystemTime,
+    /// `None` while the host still has the lock.
+    pub end: Option<SystemTime>,
+    /// Played on the local copy without the lock, see `start_offline_session`.
+    pub offline: bool,
+    /// The sessions that started meanwhile, the snapshots before the trailers existed have none.
+    pub sessions: Vec<Session>,
+}
+
+impl HostingPeriod {
+    pub fn duration(&self) -> Duration {
+        self.end
+            .unwrap_or_else(SystemTime::now)
+            .duration_since(self.start)
+            .unwrap_or_default()
+    }
+
+    pub fn players(&self) -> BTreeSet<&str> {
+        self.sessions
+            .iter()
+            .flat_map(|session| session.players.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// Part of the period that is after `since`.
+    fn duration_since(&self, since: SystemTime) -> Duration {
+        let end = self.end.unwrap_or_else(SystemTime::now);
+        end.duration_since(self.start.max(since))
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostRank {
+    pub host: String,
+    pub hosted: Duration,
+    pub periods: usize,
+}
+
+/// Who has hosted the server and when, read from the history.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Audit {
+    /// The newest first.
+    pub periods: Vec<HostingPeriod>,
+    /// Hosts of this month, the one that has hosted the longest first.
+    pub leaderboard: Vec<HostRank>,
+}
+
+impl Audit {
+    /// `lock_changes` must be the oldest first.
+    /// The sessions of the `snapshots` are added to the lock period they started in,
+    /// the offline ones are periods on their own.
+    pub fn new(lock_changes: &[LockChange], snapshots: &[Snapshot]) -> Self {
+        let mut periods: Vec<HostingPeriod> = vec![];
+        for change in lock_changes {
+            if let Some(last) = periods.last_mut().filter(|last| last.end.is_none()) {
+                last.end = Some(change.time);
+            }
+            if let Some(host) = &change.host {
+                periods.push(HostingPeriod {
+                    host: user::display(host),
+                    start: change.time,
+                    end: None,
+                    offline: false,
+                    sessions: vec![],
+                });
+            }
+        }
+
+        for snapshot in snapshots {
+            let Some(session) = Session::parse(&snapshot.message) else {
+                continue;
+            };
+            let offline = snapshot.message.starts_with(session::OFFLINE_TITLE);
+            let locked = periods.iter_mut().find(|period| {
+                !period.offline
+                    && period.start <= session.start
+                    && period.end.is_none_or(|end| session.start <= end)
+            });
+            match locked {
+                Some(period) if !offline => period.sessions.push(session),
+                _ => periods.push(HostingPeriod {
+                    // Older snapshots only have the git identity of the host
+                    host: session
+                        .host
+                        .clone()
+                        .unwrap_or_else(|| snapshot.author.clone()),
+                    start: session.start,
+                    end: Some(session.end),
+                    offline: true,
+                    sessions: vec![session],
+                }),
+            }
+        }
+        periods.sort_by_key(|period| Reverse(period.start));
+
+        let now = Local::now();
+        let month_start = Local
+            .with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+            .earliest()
+            .map(SystemTime::from)
+            .unwrap_or_else(SystemTime::now);
+        let mut ranks: HashMap<&str, HostRank> = HashMap::new();
+        for period in periods.iter() {
+            let hosted = period.duration_since(month_start);
+            if hosted.is_zero() {
+                continue;
+            }
+            let rank = ranks.entry(&period.host).or_insert_with(|| HostRank {
+                host: period.host.clone(),
+                hosted: Duration::ZERO,
+                periods: 0,
+            });
+            rank.hosted += hosted;
+            rank.periods += 1;
+        }
+        let mut leaderboard: Vec<HostRank> = ranks.into_values().collect();
+        leaderboard.sort_by_key(|rank| Reverse(rank.hosted));
+
+        Self {
+            periods,
+            leaderboard,
+        }
+    }
+
+    /// The periods as a table, one per row.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "host,start,end,minutes,offline,sessions,players,server_version,world_size_delta\n",
+        );
+        for period in self.periods.iter() {
+            let players: Vec<&str> = period.players().into_iter().collect();
+            let server_version = period
+                .sessions
+                .iter()
+                .find_map(|session| session.server_version.as_deref())
+                .unwrap_or_default();
+            let world_size_delta: i64 = period
+                .sessions
+                .iter()
+                .map(|session| session.world_size_delta)
+                .sum();
+
+            let row = [
+                period.host.clone(),
+                format_date(period.start),
+                period.end.map(format_date).unwrap_or_default(),
+                (period.duration().as_secs() / 60).to_string(),
+                period.offline.to_string(),
+                period.sessions.len().to_string(),
+                players.join(" "),
+                server_version.to_string(),
+                world_size_delta.to_string(),
+            ];
+            let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+            csv += &row.join(",");
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+/// Quoted if it has a separator, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn lock(secs: u64, host: Option<&str>) -> LockChange {
+        LockChange {
+            time: at(secs),
+            host: host.map(Into::into),
+        }
+    }
+
+    fn snapshot(title: &str, host: Option<&str>, start: u64, end: u64) -> Snapshot {
+        let session = Session {
+            host: host.map(Into::into),
+            start: at(start),
+            end: at(end),
+            players: BTreeSet::from(["Steve".to_string()]),
+            server_version: None,
+            world_size_delta: 0,
+        };
+        Snapshot {
+            time: at(end),
+            author: "Git Identity".into(),
+            message: format!("{}\n\n{}", title, session.trailers()),
+        }
+    }
+
+    #[test]
+    fn sessions_are_added_to_the_lock_period_they_started_in() {
+        let lock_changes = [
+            lock(100, Some("ip=1.1.1.1\nusername=alice")),
+            lock(200, None),
+            lock(300, Some("ip=2.2.2.2")),
+        ];
+        let snapshots = [snapshot(session::HOSTING_TITLE, Some("alice"), 150, 190)];
+        let audit = Audit::new(&lock_changes, &snapshots);
+
+        assert_eq!(audit.periods.len(), 2);
+        let (open, closed) = (&audit.periods[0], &audit.periods[1]);
+        assert_eq!(open.host, "2.2.2.2");
+        assert_eq!(open.end, None);
+        assert!(open.sessions.is_empty());
+        assert_eq!(closed.host, "alice");
+        assert_eq!((closed.start, closed.end), (at(100), Some(at(200))));
+        assert_eq!(closed.sessions.len(), 1);
+    }
+
+    #[test]
+    fn offline_sessions_are_periods_with_the_host_of_the_lock() {
+        let lock_changes = [
+            lock(100, Some("ip=1.1.1.1\nusername=alice")),
+            lock(200, None),
+        ];
+        let snapshots = [
+            snapshot(session::OFFLINE_TITLE, Some("alice"), 150, 160),
+            snapshot(session::OFFLINE_TITLE, None, 300, 400),
+        ];
+        let audit = Audit::new(&lock_changes, &snapshots);
+
+        let hosts: Vec<(&str, bool)> = audit
+            .periods
+            .iter()
+            .map(|period| (period.host.as_str(), period.offline))
+            .collect();
+        assert_eq!(
+            hosts,
+            [("Git Identity", true), ("alice", true), ("alice", false)]
+        );
+        assert!(audit.periods[2].sessions.is_empty());
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("alice"), "alice");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+
+        let audit = Audit::new(
+            &[lock(100, Some("ip=1.1.1.1\nusername=a,b")), lock(200, None)],
+            &[],
+        );
+        let csv = audit.to_csv();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[1].starts_with("\"a,b\","), "{}", rows[1]);
+    }
+}
@@ -1,31 +1,85 @@
 use super::{
-    database::{self, connect_to_database, local_files},
-    BackendUser, CommandSender,
+    database::{
+        self, connect_to_database,
+        host_status::StatusRefresher,
+        local_files,
+        session::{self, Session},
+    },
+    load_gamerules,
+    plugins::{self, Plugin},
+    BackendUser, CommandSender, GameRule, RconConfig, ServerProperties, ServerSettings,
 };
 use crate::{
-    app::Scene, ddns, process::{stream_command, self}, pull_channel::*, verify_signature::verify_signature,
+    app::{format_size, user, Scene},
+    ddns,
+    error::Error,
+    git::{self, Login},
+    manifest,
+    port_mapping::{PortMapping, PortMappingStatus},
+    process::{self, stream_command},
+    pull_channel::*,
+    reachability::{self, Reachability},
+    verify_signature::update_trusted_keys,
 };
 use std::{
+    collections::BTreeSet,
     io::{BufRead, BufReader},
+    path::{Path, PathBuf},
     process::Child,
     sync::{
         mpsc::{channel, Receiver, Sender},
         Arc, Mutex,
     },
     thread,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub enum Action {
     Database(database::Action),
     OpenServer(u8),
+    /// Opens the server from the local copy, without syncing nor locking it.
+    OpenOfflineServer(u8),
+    /// Shows the settings scene until another action is received.
+    EditSettings,
+    SaveSettings(ServerSettings),
+    /// Shows the plugins scene until another action is received.
+    ManagePlugins,
+    SetPluginEnabled(Plugin, bool),
+    /// Shows the login scene until another action is received.
+    Login,
+    SaveLogin(Login),
+    /// Shows the maintenance scene until another action is received.
+    Maintenance,
+    PruneHistory(usize),
+    /// Shows the backups scene until another action is received.
+    Backups,
+    RestoreBackup(PathBuf),
+    /// Shows the hosting log scene until another action is received.
+    HostingLog,
+    ExportHostingLog,
+    /// Asks what to do with the files found on the place of the world,
+    /// until another action is received.
+    SetAsideFolder,
+    ImportFolder,
+    KeepFolderAside,
+    /// Drops the local snapshots that conflict with the shared world.
+    DiscardLocalChanges,
+    /// Shows the cancelled scene until another action is received.
+    Cancelled,
 }
 
 pub struct BackendProcess {
     user: BackendUser,
     action_recv: Receiver<Action>,
     action: Action,
+    /// Database action to retry once the user has logged in.
+    after_login: database::Action,
+    /// The last sync could not reach the origin.
+    connection_failed: bool,
+    /// The local copy is checked before the next sync, on start and after a failure.
+    check_store: bool,
+    host_status: StatusRefresher,
 }
 
 impl BackendProcess {
@@ -58,6 +112,12 @@ impl BackendProcess {
             return Err(());
         }
         println!("{}", java_version);
+
+        // The world is not opened yet, so the files found on its place can be moved
+        if let Err(err) = database::set_aside_foreign_files() {
+            user.fatal_error(&format!("Could not check the server folder: {}", err));
+            return Err(());
+        }
         
         // TODO: Check non commited changes
         
@@ -66,44 +126,231 @@ impl BackendProcess {
             user,
             action_recv,
             action: Action::Database(database::Action::Unlock),
+            after_login: database::Action::Unlock,
+            connection_failed: false,
+            check_store: true,
+            host_status: StatusRefresher::default(),
         };
         Ok(backend)
     }
 
     fn run_backend_loop(mut self) {
+        if !database::has_push_credentials() {
+            self.action = Action::Login;
+        }
+
         let mut cooldown = Duration::ZERO;
         loop {
             match self.pull_action_channel(cooldown) {
                 Action::Database(_) => self.connect_to_database(),
                 Action::OpenServer(ram) => {
-                    self.open_server(ram);
+                    self.open_server(ram, false);
                     self.pull_action_channel(Duration::ZERO);
                     self.action = Action::Database(database::Action::Unlock);
                 }
+                Action::OpenOfflineServer(ram) => {
+                    self.open_offline_server(ram);
+                    self.pull_action_channel(Duration::ZERO);
+                    self.action = Action::Database(database::Action::Unlock);
+                }
+                Action::EditSettings => {
+                    self.edit_settings();
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::SaveSettings(settings) => {
+                    self.save_settings(&settings);
+                    self.action = Action::Database(database::Action::Lock);
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::ManagePlugins => {
+                    self.manage_plugins();
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::SetPluginEnabled(plugin, enabled) => {
+                    self.set_plugin_enabled(&plugin, enabled);
+                    self.action = Action::ManagePlugins;
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::Login => {
+                    self.login(None);
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::SaveLogin(login) => {
+                    self.save_login(&login);
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::Maintenance => {
+                    self.maintenance(None);
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::PruneHistory(keep) => {
+                    self.prune_history(keep);
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::Backups => {
+                    self.backups(None);
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::RestoreBackup(backup) => {
+                    self.restore_backup(&backup);
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::HostingLog => {
+                    self.hosting_log(None);
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::ExportHostingLog => {
+                    self.export_hosting_log();
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::SetAsideFolder => {
+                    // The scene has been left without an answer, it's asked again on the next sync
+                    self.action = Action::Database(database::Action::Unlock);
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::ImportFolder => {
+                    self.import_folder();
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::KeepFolderAside => {
+                    self.keep_folder_aside();
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::DiscardLocalChanges => {
+                    self.discard_local_changes();
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
+                Action::Cancelled => {
+                    self.cancelled();
+                    cooldown = Duration::ZERO;
+                    continue;
+                }
             }
             cooldown = Duration::from_secs(5);
         }
     }
 
     fn connect_to_database(&mut self) {
+        match database::set_aside_folder() {
+            Ok(Some(folder)) => return self.set_aside_folder(&folder, None),
+            Ok(None) => {}
+            Err(err) => {
+                return self
+                    .user
+                    .fatal_error(&format!("Could not check the server folder: {}", err))
+            }
+        }
+
         let action = match self.pull_action_channel(Duration::ZERO) {
             Action::Database(action) => action,
-            Action::OpenServer(_) => database::Action::Lock,
+            Action::OpenServer(_)
+            | Action::OpenOfflineServer(_)
+            | Action::EditSettings
+            | Action::SaveSettings(_)
+            | Action::ManagePlugins
+            | Action::SetPluginEnabled(..)
+            | Action::Login
+            | Action::SaveLogin(_)
+            | Action::Maintenance
+            | Action::PruneHistory(_)
+            | Action::Backups
+            | Action::RestoreBackup(_)
+            | Action::HostingLog
+            | Action::ExportHostingLog
+            | Action::SetAsideFolder
+            | Action::ImportFolder
+            | Action::KeepFolderAside
+            | Action::DiscardLocalChanges
+            | Action::Cancelled => database::Action::Lock,
         };
-        if let Err(err) = connect_to_database(&self.user, || action.clone()) {
-            self.user
-                .set_scene(Scene::fatal_error(&format!("{}", err)));
+        let retry = !self.connection_failed;
+        self.connection_failed = false;
+        let result = self
+            .check_store()
+            .and_then(|()| connect_to_database(&self.user, &self.host_status, retry, || action));
+        // A click that lands once the download has finished must not cancel the next poll
+        self.user.clear_cancel_request();
+        match result {
+            Ok(()) => {}
+            Err(err) if err.is_auth() => {
+                self.after_login = action;
+                self.login(Some(
+                    "The server didn't accept your credentials.\n\
+                    Check that your account can push to the server repository."
+                        .into(),
+                ));
+            }
+            Err(err) if err.is_cancelled() => self.cancelled(),
+            Err(err) if err.is_network() => {
+                // The database is polled periodically, so it will retry until it connects
+                self.connection_failed = true;
+                self.user.set_scene(Scene::ConnectionFailed {
+                    details: format!("{}", err),
+                    unsynced: database::has_pending_upload(&self.user),
+                });
+            }
+            Err(err) => {
+                // A damaged local copy may be the cause
+                self.check_store = true;
+                self.user.set_scene(Scene::fatal_error(&format!("{}", err)))
+            }
+        }
+    }
+
+    fn check_store(&mut self) -> Result<(), Error> {
+        if self.check_store {
+            database::repair_store(&self.user)?;
+            self.check_store = false;
         }
+        Ok(())
     }
 
     fn pull_action_channel(&mut self, mut timeout: Duration) -> Action {
         match self.action {
-            Action::OpenServer(_) => timeout = Duration::ZERO,
-            Action::Database(_) => {}
+            Action::OpenServer(_)
+            | Action::OpenOfflineServer(_)
+            | Action::SaveSettings(_)
+            | Action::SetPluginEnabled(..)
+            | Action::SaveLogin(_)
+            | Action::PruneHistory(_)
+            | Action::RestoreBackup(_)
+            | Action::ExportHostingLog
+            | Action::ImportFolder
+            | Action::KeepFolderAside
+            | Action::DiscardLocalChanges => timeout = Duration::ZERO,
+            Action::Database(_)
+            | Action::EditSettings
+            | Action::ManagePlugins
+            | Action::Login
+            | Action::Maintenance
+            | Action::Backups
+            | Action::HostingLog
+            | Action::SetAsideFolder
+            | Action::Cancelled => {}
         };
 
         match pull_until_last(&self.action_recv, timeout) {
-            Received::Some(action) => self.action = action,
+            Received::Some(action) => {
+                // The cancel button only stops the operation it was shown on
+                self.user.clear_cancel_request();
+                self.action = action;
+            }
             Received::Empty => {}
             Received::ChannelClosed => {
                 self.action = Action::Database(database::Action::Unlock);
@@ -114,31 +361,357 @@ impl BackendProcess {
         self.action.clone()
     }
 
-    fn open_server(&self, ram: u8) {
+    fn edit_settings(&mut self) {
+        let settings = match local_files::get_app_folder_path().and_then(ServerSettings::load) {
+            Ok(settings) => settings,
+            Err(err) => {
+                self.user
+                    .fatal_error(&format!("Could not read server settings: {}", err));
+                self.action = Action::Database(database::Action::Lock);
+                return;
+            }
+        };
+
+        self.user.set_scene(Scene::Settings {
+            settings,
+            error: None,
+        });
+
+        while let Action::EditSettings = self.pull_action_channel(Duration::from_secs(5)) {}
+    }
+
+    fn save_settings(&self, settings: &ServerSettings) {
+        if let Err(err) = database::save_server_settings(&self.user, settings) {
+            self.user
+                .fatal_error(&format!("Could not save server settings: {}", err));
+        }
+    }
+
+    fn login(&mut self, error: Option<String>) {
+        let username = git::saved_login()
+            .map(|login| login.username)
+            .unwrap_or_default();
+
+        self.user.set_scene(Scene::Login {
+            username,
+            token: "".into(),
+            error,
+        });
+
+        self.action = Action::Login;
+        while let Action::Login = self.pull_action_channel(Duration::from_secs(5)) {}
+    }
+
+    fn set_aside_folder(&mut self, folder: &Path, error: Option<String>) {
+        self.user.set_scene(Scene::SetAsideFolder {
+            path: folder.display().to_string(),
+            error,
+        });
+
+        self.action = Action::SetAsideFolder;
+        while let Action::SetAsideFolder = self.pull_action_channel(Duration::from_secs(5)) {}
+    }
+
+    fn import_folder(&mut self) {
+        match database::import_set_aside_folder(&self.user) {
+            Ok(()) => self.action = Action::Database(database::Action::Unlock),
+            Err(err) => match database::set_aside_folder() {
+                Ok(Some(folder)) => self.set_aside_folder(&folder, Some(format!("{}", err))),
+                _ => {
+                    self.user
+                        .fatal_error(&format!("Could not import the folder: {}", err));
+                    self.action = Action::Database(database::Action::Unlock);
+                }
+            },
+        }
+    }
+
+    fn keep_folder_aside(&mut self) {
+        if let Err(err) = database::keep_folder_aside() {
+            self.user.fatal_error(&format!("{}", err));
+        }
+        self.action = Action::Database(database::Action::Unlock);
+    }
+
+    fn discard_local_changes(&mut self) {
+        if let Err(err) = database::discard_local_changes(&self.user) {
+            self.user
+                .fatal_error(&format!("Could not discard the local changes: {}", err));
+        }
+        self.action = Action::Database(database::Action::Unlock);
+    }
+
+    fn cancelled(&mut self) {
+        self.user.set_scene(Scene::Cancelled);
+
+        self.action = Action::Cancelled;
+        while let Action::Cancelled = self.pull_action_channel(Duration::from_secs(5)) {}
+    }
+
+    fn save_login(&mut self, login: &Login) {
+        match git::save_login(login) {
+            Ok(()) => self.action = Action::Database(self.after_login),
+            Err(err) => self.login(Some(format!("{}", err))),
+        }
+    }
+
+    fn maintenance(&mut self, result: Option<String>) {
+        let stats = match database::history_stats(&self.user) {
+            Ok(stats) => stats,
+            Err(err) => {
+                self.user
+                    .fatal_error(&format!("Could not read the history: {}", err));
+                self.action = Action::Database(database::Action::Lock);
+                return;
+            }
+        };
+
+        self.user.set_scene(Scene::Maintenance {
+            size: stats.size,
+            snapshots: stats.snapshots,
+            keep: database::DEFAULT_KEPT_SNAPSHOTS,
+            result,
+        });
+
+        self.action = Action::Maintenance;
+        while let Action::Maintenance = self.pull_action_channel(Duration::from_secs(5)) {}
+    }
+
+    fn prune_history(&mut self, keep: usize) {
+        match database::prune_history(&self.user, keep) {
+            Ok((before, after)) if before.snapshots == after.snapshots => {
+                self.maintenance(Some("There was nothing to prune.".into()));
+            }
+            Ok((before, after)) => {
+                self.maintenance(Some(format!(
+                    "Removed {} snapshots, the world repository went from {} to {}.",
+                    before.snapshots - after.snapshots,
+                    format_size(before.size),
+                    format_size(after.size),
+                )));
+            }
+            Err(err) => {
+                self.user
+                    .fatal_error(&format!("Could not prune the history: {}", err));
+                self.action = Action::Database(database::Action::Lock);
+            }
+        }
+    }
+
+    fn backups(&mut self, result: Option<String>) {
+        let backups = match database::list_backups() {
+            Ok(backups) => backups,
+            Err(err) => {
+                self.user
+                    .fatal_error(&format!("Could not list the backups: {}", err));
+                self.action = Action::Database(database::Action::Lock);
+                return;
+            }
+        };
+
+        self.user.set_scene(Scene::Backups { backups, result });
+
+        self.action = Action::Backups;
+        while let Action::Backups = self.pull_action_channel(Duration::from_secs(5)) {}
+    }
+
+    fn restore_backup(&mut self, backup: &Path) {
+        match database::restore_backup(&self.user, backup) {
+            Ok(()) => self.backups(Some(
+                "The backup has been restored, it will be uploaded when the server is unlocked."
+                    .into(),
+            )),
+            Err(err) => {
+                self.user
+                    .fatal_error(&format!("Could not restore the backup: {}", err));
+                self.action = Action::Database(database::Action::Lock);
+            }
+        }
+    }
+
+    fn hosting_log(&mut self, result: Option<String>) {
+        let audit = match database::hosting_log(&self.user) {
+            Ok(audit) => audit,
+            Err(err) => {
+                self.user
+                    .fatal_error(&format!("Could not read the history: {}", err));
+                self.action = Action::Database(database::Action::Lock);
+                return;
+            }
+        };
+
+        self.user.set_scene(Scene::HostingLog { audit, result });
+
+        self.action = Action::HostingLog;
+        while let Action::HostingLog = self.pull_action_channel(Duration::from_secs(5)) {}
+    }
+
+    fn export_hosting_log(&mut self) {
+        match database::export_hosting_log(&self.user) {
+            Ok(path) => self.hosting_log(Some(format!("Exported to {}", path.display()))),
+            Err(err) => self.hosting_log(Some(format!("Could not export the log: {}", err))),
+        }
+    }
+
+    fn manage_plugins(&mut self) {
+        if let Ok(server_path) = local_files::get_app_folder_path() {
+            let _ = local_files::get_trusted_keys_cache_path()
+                .and_then(|cache| update_trusted_keys(&server_path, cache));
+        }
+
+        let plugins = match local_files::get_app_folder_path().and_then(plugins::list_plugins) {
+            Ok(plugins) => plugins,
+            Err(err) => {
+                self.user
+                    .fatal_error(&format!("Could not list plugins: {}", err));
+                self.action = Action::Database(database::Action::Lock);
+                return;
+            }
+        };
+
+        self.user.set_scene(Scene::Plugins { plugins });
+
+        while let Action::ManagePlugins = self.pull_action_channel(Duration::from_secs(5)) {}
+    }
+
+    fn set_plugin_enabled(&self, plugin: &Plugin, enabled: bool) {
+        if let Err(err) = database::set_plugin_enabled(&self.user, plugin, enabled) {
+            self.user
+                .fatal_error(&format!("Could not update {}: {}", plugin.name, err));
+        }
+    }
+
+    /// The changes are saved as a snapshot when the server stops,
+    /// and uploaded on the next sync.
+    fn open_offline_server(&self, ram: u8) {
+        if let Err(err) = database::start_offline_session(&self.user) {
+            self.user
+                .fatal_error(&format!("Could not start the offline session: {}", err));
+            return;
+        }
+
+        self.open_server(ram, true);
+
+        if let Err(err) = database::end_offline_session(&self.user) {
+            self.user
+                .fatal_error(&format!("Could not save the offline session: {}", err));
+        }
+    }
+
+    /// When `offline`, the dns is not updated because it may not be reachable.
+    fn open_server(&self, ram: u8, offline: bool) {
         match local_files::get_app_folder_path() {
             Err(err) => self.user.fatal_error(&format!("{}", err)),
             Ok(server_path) => {
-                if !verify_signature(server_path.join("mc_server.jar")) {
+                if let Err(err) = local_files::get_trusted_keys_cache_path()
+                    .and_then(|cache| update_trusted_keys(&server_path, cache))
+                {
                     self.user.set_scene(Scene::Error {
                         title: "Error".into(),
-                        message: "The signature is not valid!\n\
-                            There is a signature to prevent other hosters from injecting viruses in the server.\
-                            If the signature doesn't validate, it means that the files have been modified without authorization.\n\
-                            Contact with a moderator!".into(),
-                        details: "".into(),
+                        message: "The list of trusted keys is not valid!\n\
+                            Contact with a moderator!"
+                            .into(),
+                        details: format!("{}", err),
                     });
                     return;
                 }
 
-                if let Err(error) = ddns::update() {
-                    self.user.set_scene(Scene::Error {
-                        title: "Error".into(),
-                        message: "Could not update dns.\nContact with a moderator.\n".into(),
-                        details: format!("{}", error),
-                    });
-                    return;
+                match manifest::check(&server_path) {
+                    Ok(untrusted) if untrusted.is_empty() => {}
+                    Ok(untrusted) => {
+                        self.user.set_scene(Scene::Error {
+                            title: "Error".into(),
+                            message: "The signature is not valid!\n\
+                                There is a signature to prevent other hosters from injecting viruses in the server.\
+                                If the signature doesn't validate, it means that the files have been modified without authorization.\n\
+                                Contact with a moderator!".into(),
+                            details: untrusted
+                                .iter()
+                                .map(|file| file.to_string())
+                                .collect::<Vec<_>>()
+                                .join("\n"),
+                        });
+                        return;
+                    }
+                    Err(err) => {
+                        self.user
+                            .fatal_error(&format!("Could not check the server files: {}", err));
+                        return;
+                    }
                 }
 
+                let gamerules = match load_gamerules(&server_path) {
+                    Ok(gamerules) => gamerules,
+                    Err(err) => {
+                        self.user
+                            .fatal_error(&format!("Could not read gamerules: {}", err));
+                        return;
+                    }
+                };
+
+                let server_port = match ServerProperties::load(&server_path) {
+                    Ok(properties) => properties.server_port(),
+                    Err(err) => {
+                        self.user
+                            .fatal_error(&format!("Could not read server.properties: {}", err));
+                        return;
+                    }
+                };
+
+                let rcon_config = match RconConfig::enable(&server_path) {
+                    Ok(config) => config,
+                    Err(err) => {
+                        self.user
+                            .fatal_error(&format!("Could not enable rcon: {}", err));
+                        return;
+                    }
+                };
+
+                if !offline {
+                    if let Err(error) = ddns::update() {
+                        self.user.set_scene(Scene::Error {
+                            title: "Error".into(),
+                            message: "Could not update dns.\nContact with a moderator.\n".into(),
+                            details: format!("{}", error),
+                        });
+                        return;
+                    }
+                }
+
+                let port_mapping_status = Arc::new(Mutex::new(PortMappingStatus::Requesting));
+                let _port_mapping = match PortMapping::request(server_port) {
+                    Ok(mapping) => {
+                        if let Ok(mut status) = port_mapping_status.lock() {
+                            *status = PortMappingStatus::Mapped {
+                                method: mapping.method(),
+                            };
+                        }
+                        let user = self.user.clone();
+                        let status = port_mapping_status.clone();
+                        Some(mapping.keep_alive(move |err| {
+                            if let Ok(mut status) = status.lock() {
+                                *status = PortMappingStatus::Unavailable(format!("{}", err));
+                            }
+                            user.request_repaint();
+                        }))
+                    }
+                    Err(err) => {
+                        if let Ok(mut status) = port_mapping_status.lock() {
+                            *status = PortMappingStatus::Unavailable(format!("{}", err));
+                        }
+                        None
+                    }
+                };
+
+                let size_before = match session::world_size(&server_path) {
+                    Ok(size) => size,
+                    Err(err) => {
+                        self.user
+                            .fatal_error(&format!("Could not read the world size: {}", err));
+                        return;
+                    }
+                };
+
                 let start_server_command = format!(
                     r#"cd "{}"; java -Xmx{1}g -Xms{1}g -jar mc_server.jar nogui"#,
                     server_path.display(),
@@ -146,39 +719,103 @@ impl BackendProcess {
                 );
 
                 match stream_command("powershell", ["-c", &start_server_command]) {
-                    Ok(process) => self.run_server(process),
+                    Ok(process) => {
+                        let session = self.run_server(
+                            process,
+                            &gamerules,
+                            server_port,
+                            port_mapping_status,
+                            rcon_config,
+                            offline,
+                        );
+                        // Saved first, so the session is not lost if rcon can't be disabled
+                        if let Some(session) = session {
+                            self.save_session(session, &server_path, size_before);
+                        }
+                        if let Err(err) = RconConfig::disable(&server_path) {
+                            self.user
+                                .fatal_error(&format!("Could not disable rcon: {}", err));
+                        }
+                    }
                     Err(err) => self.user.fatal_error(&format!("{}", err)),
                 }
             }
         }
     }
 
-    fn run_server(&self, mut process: Child) {
+    /// Keeps what happened on the server until the snapshot that saves the world is made.
+    fn save_session(&self, mut session: Session, server_path: &Path, size_before: u64) {
+        session.end = SystemTime::now();
+        let saved = session::world_size(server_path).and_then(|size| {
+            session.world_size_delta = size as i64 - size_before as i64;
+            database::save_session(&session)
+        });
+        if let Err(err) = saved {
+            self.user
+                .fatal_error(&format!("Could not save the session: {}", err));
+        }
+    }
+
+    /// The saved gamerules are queued on the server console,
+    /// so they are applied as soon as the world has loaded.
+    /// The port mapping is removed when `open_server` returns, after the server has stopped.
+    ///
+    /// Returns the players and the version of the server,
+    /// or `None` if the server could not be followed.
+    fn run_server(
+        &self,
+        mut process: Child,
+        gamerules: &[GameRule],
+        server_port: u16,
+        port_mapping: Arc<Mutex<PortMappingStatus>>,
+        rcon_config: RconConfig,
+        offline: bool,
+    ) -> Option<Session> {
         let Some(stdout) = process.stdout.take() else {
 			self.user.fatal_error("Could not get stdout from the Minecraft Server process.");
-			return;
+			return None;
 		};
 
         let Some(stderr) = process.stderr.take() else {
             self.user.fatal_error("Could not get stderr from the Minecraft Server process.");
-            return;
+            return None;
         };
 
         let Some(stdin) = process.stdin.take() else {
             self.user.fatal_error("Could not get stdin from the Minecraft Server process.");
-            return;
+            return None;
+        };
+
+        let mut session = Session {
+            host: user::display_name(),
+            start: SystemTime::now(),
+            end: SystemTime::now(),
+            players: BTreeSet::new(),
+            server_version: None,
+            world_size_delta: 0,
         };
 
         let ui_chat = Arc::new(Mutex::new("".into()));
         let ui_players = Arc::new(Mutex::new(vec![]));
         let ui_tps = Arc::new(Mutex::new(20.));
+        let ui_reachability = Arc::new(Mutex::new(Reachability::Checking));
+
+        let mut command_sender =
+            CommandSender::new(stdin, rcon_config, ui_chat.clone(), self.user.clone());
+        for rule in gamerules {
+            let _ = command_sender.send_gamerule(rule);
+        }
 
         self.user.set_scene(Scene::Hosting {
             chat: ui_chat.clone(),
             players: ui_players.clone(),
             tps: ui_tps.clone(),
+            server_port,
+            port_mapping,
+            reachability: ui_reachability.clone(),
             command: "".into(),
-            command_sender: CommandSender::new(stdin),
+            command_sender,
+            offline,
         });
 
         let stdout_reader = BufReader::new(stdout);
@@ -189,7 +826,7 @@ impl BackendProcess {
                     ConsoleLog::Chat { msg } => {
                         let Ok(mut out) = ui_chat.lock() else {
                             self.user.fatal_error("Could not lock server output.");
-                            return;
+                            return None;
                         };
 
                         *out += &msg;
@@ -199,11 +836,12 @@ impl BackendProcess {
                     ConsoleLog::Joined { player_name, msg } => {
                         let Ok(mut out) = ui_chat.lock() else {
                             self.user.fatal_error("Could not lock server output.");
-                            return;
+                            return None;
                         };
 
                         *out += &msg;
 
+                        session.players.insert(player_name.clone());
                         if let Ok(mut players_list) = ui_players.lock() {
                             players_list.push(player_name);
                         }
@@ -213,7 +851,7 @@ impl BackendProcess {
                     ConsoleLog::Left { player_name, msg } => {
                         let Ok(mut out) = ui_chat.lock() else {
                             self.user.fatal_error("Could not lock server output.");
-                            return;
+                            return None;
                         };
 
                         *out += &msg;
@@ -231,13 +869,25 @@ impl BackendProcess {
                     ConsoleLog::Tps { tps } => {
                         let Ok(mut ui_tps) = ui_tps.lock() else {
                             self.user.fatal_error("Could not lock server output.");
-                            return;
+                            return None;
                         };
 
                         *ui_tps = tps;
 
                         self.user.request_repaint();
                     }
+                    ConsoleLog::Version { version } => session.server_version = Some(version),
+                    ConsoleLog::Started => {
+                        let user = self.user.clone();
+                        let ui_reachability = ui_reachability.clone();
+                        thread::spawn(move || {
+                            let reachability = reachability::check(server_port);
+                            if let Ok(mut ui_reachability) = ui_reachability.lock() {
+                                *ui_reachability = reachability;
+                            }
+                            user.request_repaint();
+                        });
+                    }
                     ConsoleLog::Other => {}
                 }
             }
@@ -263,6 +913,8 @@ impl BackendProcess {
                 }
             }
         };
+
+        Some(session)
     }
 }
 
@@ -271,6 +923,8 @@ enum ConsoleLog {
     Joined { player_name: String, msg: String },
     Left { player_name: String, msg: String },
     Tps { tps: f32 },
+    Version { version: String },
+    Started,
     Other,
 }
 
@@ -304,6 +958,12 @@ fn parse_console_log(line: &str) -> ConsoleLog {
             },
             None => ConsoleLog::Other,
         }
+    } else if let Some(version) = line[15..].strip_prefix(": Starting minecraft server version ") {
+        ConsoleLog::Version {
+            version: version.trim().to_string(),
+        }
+    } else if line[15..].starts_with(": Done (") {
+        ConsoleLog::Started
     } else if &line[15..30] == ": [Not Secure] " {
         ConsoleLog::Chat {
             msg: format!("{}] {}\n", &line[0..9], &line[30..]),
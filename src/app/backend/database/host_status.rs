@@ -0,0 +1,61 @@
+use super::BackendUser;
+use crate::{
+    ddns,
+    server_list_ping::{self, ServerStatus},
+};
+use std::{
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::Duration,
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Status of the host's server, or why it could not be reached.
+/// `None` until the first ping answers.
+pub type SharedStatus = Arc<Status>;
+type Status = Mutex<Option<Result<ServerStatus, String>>>;
+
+/// Pings the server of the host from its own thread,
+/// so polling the lock is not delayed by the ping timeouts.
+#[derive(Default)]
+pub struct StatusRefresher {
+    status: Mutex<Weak<Status>>,
+}
+
+impl StatusRefresher {
+    /// The status shown on the scene. It's refreshed while a scene has it,
+    /// and the same one is returned on every poll so it doesn't go back to unknown.
+    pub fn status(&self, user: &BackendUser, port: u16) -> SharedStatus {
+        let Ok(mut current) = self.status.lock() else {
+            return Arc::new(Mutex::new(Some(Err("Status lock poisoned".into()))));
+        };
+        if let Some(status) = current.upgrade() {
+            return status;
+        }
+
+        let status: SharedStatus = Arc::new(Mutex::new(None));
+        *current = Arc::downgrade(&status);
+
+        let watched = Arc::downgrade(&status);
+        let user = user.clone();
+        thread::spawn(move || loop {
+            let result =
+                server_list_ping::ping(ddns::HOSTNAME, port).map_err(|err| format!("{}", err));
+
+            // Nobody shows it anymore
+            let Some(status) = watched.upgrade() else {
+                return;
+            };
+            if let Ok(mut status) = status.lock() {
+                *status = Some(result);
+            }
+            drop(status);
+            user.request_repaint();
+
+            thread::sleep(REFRESH_INTERVAL);
+        });
+
+        status
+    }
+}
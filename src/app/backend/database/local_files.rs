@@ -14,10 +14,10 @@ fn create_hidden_folder(path: &str, folder_name: &str) -> io::Result<()> {
     }
 }
 
+/// Hidden folder on APP_DATA with the local files of the app.
 #[cfg(windows)]
-pub fn get_app_folder_path() -> io::Result<PathBuf> {
+pub fn get_app_data_path() -> io::Result<PathBuf> {
     const APP_FOLDER_NAME: &str = "Octova - Shared Minecraft Server";
-    const SERVER_NAME: &str = "Vsinf";
 
     let Some(base_dirs) = directories::BaseDirs::new() else {
         return Err(io::Error::new(io::ErrorKind::NotFound, "Could not found APP_DATA directory"));
@@ -36,37 +36,61 @@ pub fn get_app_folder_path() -> io::Result<PathBuf> {
         create_hidden_folder(app_data_path, APP_FOLDER_NAME)?;
     }
 
-    let server_folder = app_folder.join(SERVER_NAME);
+    Ok(app_folder)
+}
+
+#[cfg(windows)]
+pub fn get_app_folder_path() -> io::Result<PathBuf> {
+    const SERVER_NAME: &str = "Vsinf";
+
+    let server_folder = get_app_data_path()?.join(SERVER_NAME);
     std::fs::create_dir_all(&server_folder)?;
     Ok(server_folder)
 }
 
-pub const HOSTER_FILE_LOCAL_PATH: &str = "hoster.txt";
-pub fn set_current_host(path: &PathBuf, host_id: Option<&str>) -> io::Result<()> {
-    let file_path = path.join(HOSTER_FILE_LOCAL_PATH);
-    match host_id {
-        Some(id) => std::fs::write(file_path, id),
-        None => match std::fs::remove_file(file_path) {
-            Ok(()) => Ok(()),
-            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
-            Err(err) => Err(err),
-        },
-    }
+/// Last trusted keys list accepted by this machine.
+pub fn get_trusted_keys_cache_path() -> io::Result<PathBuf> {
+    Ok(get_app_data_path()?.join("trusted_keys.txt"))
+}
+
+/// Where the world of the server is shared, see `StoreLocation::parse`.
+/// Without it the default GitHub repository is used.
+pub fn get_server_profile_path() -> io::Result<PathBuf> {
+    Ok(get_app_data_path()?.join("server_profile.txt"))
 }
 
-pub fn load_current_host(path: &PathBuf) -> io::Result<Option<String>> {
-    let path = path.join(HOSTER_FILE_LOCAL_PATH);
+/// Exists while the changes of an offline session have not been uploaded.
+pub fn get_offline_session_path() -> io::Result<PathBuf> {
+    Ok(get_app_data_path()?.join("offline_session.txt"))
+}
 
-    if path.is_file() {
-        let content = std::fs::read(path)?;
-        let host_id = String::from_utf8_lossy(&content).to_string();
+/// The last hosting session, kept until the snapshot that saves its world is made.
+pub fn get_session_path() -> io::Result<PathBuf> {
+    Ok(get_app_data_path()?.join("session.txt"))
+}
 
-        if host_id.len() == 0 {
-            Ok(None)
-        } else {
-            Ok(Some(host_id))
-        }
-    } else {
-        Ok(None)
-    }
+/// Password of the RCON of the server opened on this computer.
+/// It's never uploaded, the server only gets it while it runs.
+pub fn get_rcon_password_path() -> io::Result<PathBuf> {
+    Ok(get_app_data_path()?.join("rcon_password.txt"))
+}
+
+/// Where the exported files are written, the downloads folder if there is one.
+pub fn get_export_folder_path() -> io::Result<PathBuf> {
+    let Some(dirs) = directories::UserDirs::new() else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Could not found the user directory",
+        ));
+    };
+    Ok(dirs
+        .download_dir()
+        .unwrap_or_else(|| dirs.home_dir())
+        .to_path_buf())
+}
+
+/// Path of the folder that has been moved from the place of the world,
+/// while the user has not decided if it's imported or kept aside.
+pub fn get_set_aside_folder_path() -> io::Result<PathBuf> {
+    Ok(get_app_data_path()?.join("set_aside_folder.txt"))
 }
@@ -1,47 +1,186 @@
+pub mod host_status;
 pub mod local_files;
+pub mod session;
 
-use self::local_files::HOSTER_FILE_LOCAL_PATH;
+use self::host_status::StatusRefresher;
+use self::session::Session;
+use super::audit::Audit;
+use super::plugins::{self, Plugin};
+use super::server_settings::{ServerProperties, ServerSettings};
 use super::*;
 use crate::app::user;
+use crate::backup::{self, Backup};
 use crate::error::Error;
-use crate::git::{Git, MergeStatus, StatusReporter};
+use crate::git::{Git, StatusReporter, TransferProgress};
+use crate::world_store::{self, MergeStatus, StoreLocation, WorldStore};
+use std::{
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
 
 const SERVER_REPO_URL: &str = "https://github.com/otcova-helper/mc-pasqua";
 
-impl StatusReporter for &BackendUser {
+/// Times a download or an upload is tried before giving up.
+const NETWORK_TRIES: u32 = 6;
+/// Doubled after every failed try.
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Times a sync starts again because someone else uploaded first.
+const SYNC_TRIES: u32 = 5;
+
+impl StatusReporter for BackendUser {
     fn status_change(&self, operation: &'static str, progress: Option<f32>) {
         self.report_progress(operation.into(), progress.unwrap_or(0.))
     }
+
+    fn status_count(&self, operation: &'static str, done: usize, total: usize) {
+        self.report_progress(
+            format!("{} ({}/{} files)", operation, done, total),
+            done as f32 / total.max(1) as f32,
+        )
+    }
+
+    fn transfer_progress(&self, operation: &'static str, progress: &TransferProgress) {
+        self.set_scene(Scene::Transfer {
+            title: operation.into(),
+            progress: progress.clone(),
+        })
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.is_cancel_requested()
+    }
 }
 
-fn try_sync_with_origin<F>(user: &BackendUser, on_sync: F) -> Result<(), Error>
+/// Where the world is shared, from the server profile.
+fn load_store_location() -> Result<StoreLocation, Error> {
+    match std::fs::read_to_string(local_files::get_server_profile_path()?) {
+        Ok(profile) => StoreLocation::parse(&profile),
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(StoreLocation::Git {
+            url: SERVER_REPO_URL.into(),
+        }),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn open_store(user: &BackendUser) -> Result<Box<dyn WorldStore>, Error> {
+    world_store::open(
+        user.clone(),
+        &local_files::get_app_folder_path()?,
+        &load_store_location()?,
+    )
+}
+
+/// Opens the local copy and repairs it if it's damaged.
+pub fn repair_store(user: &BackendUser) -> Result<(), Error> {
+    world_store::open_repaired(
+        user.clone(),
+        &local_files::get_app_folder_path()?,
+        &load_store_location()?,
+    )?;
+    Ok(())
+}
+
+/// History maintenance is only possible when the world is on git.
+fn open_git(user: &BackendUser) -> Result<Git<BackendUser>, Error> {
+    let StoreLocation::Git { url } = load_store_location()? else {
+        return Err(Error::from_str(
+            "The history can only be managed when the world is shared with git",
+        ));
+    };
+    Git::new(user.clone(), &local_files::get_app_folder_path()?, &url)
+}
+
+/// Runs `operation` again while it fails with a transient error, waiting longer each time.
+/// The scene stays as loading meanwhile, so the app can't be closed.
+/// Without `retry` it's only tried once.
+fn with_retries<T, F>(
+    user: &BackendUser,
+    retry: bool,
+    description: &str,
+    mut operation: F,
+) -> Result<T, Error>
+where
+    F: FnMut() -> Result<T, Error>,
+{
+    let max_tries = if retry { NETWORK_TRIES } else { 1 };
+    let mut delay = FIRST_RETRY_DELAY;
+    let mut tries = 1;
+    loop {
+        match operation() {
+            Err(err) if err.is_transient() && tries < max_tries => {
+                user.report_progress(
+                    format!(
+                        "{} failed, retrying in {}s ({}/{}).\nDon't close the app.",
+                        description,
+                        delay.as_secs(),
+                        tries,
+                        max_tries - 1
+                    ),
+                    0.,
+                );
+                thread::sleep(delay);
+                delay *= 2;
+                tries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Downloads the shared world, merges it and uploads the local snapshots.
+/// It starts again if someone uploaded meanwhile, up to `SYNC_TRIES` times.
+fn try_sync_with_origin<F>(user: &BackendUser, retry: bool, on_sync: F) -> Result<(), Error>
 where
-    F: FnOnce(&Git<&BackendUser>) -> Result<(), Error>,
+    F: FnOnce(&dyn WorldStore) -> Result<(), Error>,
 {
-    let local_files_path = local_files::get_app_folder_path()?;
-    let database = Git::<&BackendUser>::new(user, &local_files_path, SERVER_REPO_URL)?;
+    for _ in 0..SYNC_TRIES {
+        let database = open_store(user)?;
 
-    database.commit_all("before try_sync")?;
+        commit_local_changes(database.as_ref(), "Local changes", session::HOSTING_TITLE)?;
 
-    match database.pull()? {
-        MergeStatus::Conflicts(conflicts) => {
-            if conflicts.len() == 1 && conflicts[0] == HOSTER_FILE_LOCAL_PATH
-            {
-                database.reset_hard_to_origin()?;
-                try_sync_with_origin(user, on_sync)
-            } else {
+        match with_retries(user, retry, "Downloading", || database.pull())? {
+            MergeStatus::Conflicts(conflicts) => {
                 user.set_scene(Scene::RepoConflicts {
                     conflicts_count: conflicts.len(),
                 });
 
-                Ok(())
+                return Ok(());
+            }
+            MergeStatus::Ok => {
+                // The lock is changed on `on_sync`, so it's never released before the world is uploaded
+                match with_retries(user, retry, "Uploading", || database.push()) {
+                    // The push only succeeds if the origin has not moved since the pull.
+                    // If someone pushed first, like another user taking the lock at the same time,
+                    // their snapshot is downloaded and everything is decided again.
+                    Err(err) if err.is_push_rejected() => {}
+                    result => {
+                        result?;
+                        end_unsynced_session()?;
+                        return on_sync(database.as_ref());
+                    }
+                }
+            }
+            MergeStatus::HistoryRewritten => {
+                // Someone pruned the history. If there is nothing to upload
+                // the old history can be dropped by cloning again.
+                let changes = database.changed_files_from_origin()?;
+                if changes.is_empty() {
+                    database.reclone()?;
+                } else {
+                    user.set_scene(Scene::RepoConflicts {
+                        conflicts_count: changes.len(),
+                    });
+
+                    return Ok(());
+                }
             }
-        }
-        MergeStatus::Ok => {
-            database.push()?;
-            on_sync(&database)
         }
     }
+
+    Err(Error::from_str(
+        "Someone else kept uploading at the same time, try again later",
+    ))
 }
 
 #[derive(Copy, Clone)]
@@ -53,36 +192,377 @@ pub enum Action {
     Lock,
 }
 
-pub fn connect_to_database<F>(user: &BackendUser, mut on_sync: F) -> Result<(), Error>
+/// If false, the user must log in before pushing.
+pub fn has_push_credentials() -> bool {
+    match load_store_location() {
+        Ok(StoreLocation::Git { url }) => git::has_credentials(&url),
+        _ => true,
+    }
+}
+
+/// Reads the lock and takes or releases it as the action asks.
+/// The world is only synced when the lock changes hands, polling only fetches the lock.
+///
+/// Network errors are retried if `retry`,
+/// it's disabled when the connection is known to be down to not block the ui.
+pub fn connect_to_database<F>(
+    user: &BackendUser,
+    host_status: &StatusRefresher,
+    retry: bool,
+    mut on_sync: F,
+) -> Result<(), Error>
 where
     F: FnMut() -> Action,
 {
-    try_sync_with_origin(user, move |database| {
-        let action = on_sync();
-
-        let current_host = local_files::load_current_host(database.work_dir())?;
-        let user_id = user::id(git::get_username().ok());
-
-        if current_host.as_ref() == Some(&user_id) {
-            match action {
-                Action::Lock => user.set_scene(Scene::SelfLocked),
-                Action::Unlock => {
-                    local_files::set_current_host(database.work_dir(), None)?;
-                    connect_to_database(user, on_sync)?;
+    let database = open_store(user)?;
+    let action = on_sync();
+
+    let current_host = with_retries(user, retry, "Checking the lock", || database.fetch_lock())?;
+    let user_id = user::id(git::get_username().ok());
+
+    if current_host.as_ref() == Some(&user_id) {
+        match action {
+            Action::Lock => user.set_scene(Scene::SelfLocked),
+            Action::Unlock => {
+                // The world is uploaded before the lock is released
+                try_sync_with_origin(user, retry, move |database| {
+                    match with_retries(user, retry, "Unlocking", || database.push_lock(None)) {
+                        Err(err) if err.is_push_rejected() => {}
+                        result => result?,
+                    }
+                    connect_to_database(user, host_status, retry, on_sync)
+                })?;
+            }
+        }
+    } else if let Some(host_id) = current_host {
+        let port = ServerProperties::load(database.work_dir())?.server_port();
+        let status = host_status.status(user, port);
+        user.set_scene(Scene::SomeoneLocked { host_id, status });
+    } else {
+        match action {
+            Action::Unlock => {
+                // Nobody is hosting, so the changes that could not be uploaded can go now
+                if has_unsynced_session() || database.has_unpushed_snapshots()? {
+                    try_sync_with_origin(user, retry, |_| {
+                        user.set_scene(Scene::Unlocked);
+                        Ok(())
+                    })?;
+                } else {
+                    user.set_scene(Scene::Unlocked);
                 }
             }
-        } else if let Some(host_id) = current_host {
-            user.set_scene(Scene::SomeoneLocked { host_id });
-        } else {
-            match action {
-                Action::Unlock => user.set_scene(Scene::Unlocked),
-                Action::Lock => {
-                    local_files::set_current_host(database.work_dir(), Some(&user_id))?;
-                    connect_to_database(user, on_sync)?;
+            Action::Lock => {
+                match with_retries(user, retry, "Locking", || {
+                    database.push_lock(Some(&user_id))
+                }) {
+                    // Someone else took it at the same time, the lock is read again
+                    Err(err) if err.is_push_rejected() => {
+                        connect_to_database(user, host_status, retry, on_sync)?
+                    }
+                    result => {
+                        result?;
+                        // The world is only downloaded by the one that is going to host
+                        let mut synced = false;
+                        let sync = try_sync_with_origin(user, retry, |_| {
+                            synced = true;
+                            Ok(())
+                        });
+                        if sync.is_err() || !synced {
+                            // Without the world nobody would host while the lock is held.
+                            // If it can't be released now, it's the user's on the next poll.
+                            let _ = release_lock(user, &user_id);
+                            return sync;
+                        }
+                        connect_to_database(user, host_status, retry, on_sync)?;
+                    }
                 }
             }
         }
+    }
+
+    Ok(())
+}
+
+/// Unlocks the server if `user_id` still holds the lock.
+fn release_lock(user: &BackendUser, user_id: &str) -> Result<(), Error> {
+    // Cancelling the download must not cancel the release too
+    user.clear_cancel_request();
+
+    let database = open_store(user)?;
+    if database.fetch_lock()?.as_deref() == Some(user_id) {
+        database.push_lock(None)?;
+    }
+    Ok(())
+}
+
+/// True if an offline session has been played and it's not uploaded yet.
+pub fn has_unsynced_session() -> bool {
+    local_files::get_offline_session_path()
+        .map(|path| path.exists())
+        .unwrap_or(false)
+}
+
+/// True if there are local changes that have not been uploaded,
+/// like the world of a session whose upload failed.
+pub fn has_pending_upload(user: &BackendUser) -> bool {
+    has_unsynced_session()
+        || open_store(user)
+            .and_then(|database| database.has_unpushed_snapshots())
+            .unwrap_or(false)
+}
+
+/// Marks the local world as unsynced before playing on it without the origin.
+/// The server is not locked, so the changes are merged like any other on the next sync.
+pub fn start_offline_session(user: &BackendUser) -> Result<(), Error> {
+    let database = open_store(user)?;
+    commit_local_changes(
+        database.as_ref(),
+        "Local changes before playing offline",
+        session::HOSTING_TITLE,
+    )?;
+
+    let started = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+    std::fs::write(
+        local_files::get_offline_session_path()?,
+        started.to_string(),
+    )?;
+    Ok(())
+}
+
+/// Saves the offline changes as a snapshot, they are uploaded on the next sync.
+pub fn end_offline_session(user: &BackendUser) -> Result<(), Error> {
+    let database = open_store(user)?;
+    commit_local_changes(
+        database.as_ref(),
+        session::OFFLINE_TITLE,
+        session::OFFLINE_TITLE,
+    )
+}
+
+/// Keeps what happened while hosting until the world is saved as a snapshot.
+pub fn save_session(session: &Session) -> Result<(), Error> {
+    session.save(&local_files::get_session_path()?)
+}
+
+/// Saves the local changes as a snapshot with `message`.
+/// If a session has been hosted since the last snapshot,
+/// the snapshot is titled `session_title` and has the session as trailers,
+/// and it's made even if there aren't changes so the session is not lost.
+fn commit_local_changes(
+    database: &dyn WorldStore,
+    message: &str,
+    session_title: &str,
+) -> Result<(), Error> {
+    // Left there if the app was closed while hosting
+    RconConfig::disable(database.work_dir())?;
+
+    let session_path = local_files::get_session_path()?;
+    match Session::load(&session_path)? {
+        Some(session) => {
+            // Forced, the changes could have been saved already by another snapshot
+            let message = format!("{}\n\n{}", session_title, session.trailers());
+            database.commit_all_forced(&message)?;
+            std::fs::remove_file(session_path)?;
+            Ok(())
+        }
+        None => database.commit_all(message),
+    }
+}
+
+/// Makes the local world equal to the shared one, to leave a conflict.
+/// The local changes are backed up before being dropped.
+pub fn discard_local_changes(user: &BackendUser) -> Result<(), Error> {
+    let database = open_store(user)?;
+    database.reset()?;
+    end_unsynced_session()
+}
+
+fn end_unsynced_session() -> Result<(), Error> {
+    match std::fs::remove_file(local_files::get_offline_session_path()?) {
+        Ok(()) => Ok(()),
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Moves the files found on the place of the world aside if they are not a local copy,
+/// like a world that was there before installing the app.
+/// It must be done before the first setup, later the world may be in use.
+pub fn set_aside_foreign_files() -> Result<(), Error> {
+    let work_dir = local_files::get_app_folder_path()?;
+    if let Some(aside) = world_store::set_aside_foreign_files(&work_dir)? {
+        std::fs::write(
+            local_files::get_set_aside_folder_path()?,
+            aside.to_string_lossy().as_bytes(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Folder where the files found on the place of the world have been moved,
+/// until the user decides what to do with them.
+pub fn set_aside_folder() -> Result<Option<PathBuf>, Error> {
+    match std::fs::read_to_string(local_files::get_set_aside_folder_path()?) {
+        Ok(path) => Ok(Some(PathBuf::from(path)).filter(|path| path.exists())),
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Leaves the set aside folder where it is, and stops asking about it.
+pub fn keep_folder_aside() -> Result<(), Error> {
+    match std::fs::remove_file(local_files::get_set_aside_folder_path()?) {
+        Ok(()) => Ok(()),
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Uploads the set aside folder as the world of a new shared server.
+/// The shared server must not have a world yet.
+pub fn import_set_aside_folder(user: &BackendUser) -> Result<(), Error> {
+    let Some(aside) = set_aside_folder()? else {
+        return Err(Error::from_str("There isn't any folder to import"));
+    };
+    let database = open_store(user)?;
+    if !backup::world_files(database.work_dir())?.is_empty() {
+        return Err(Error::from_str(
+            "The shared server already has a world, only a new one can be imported",
+        ));
+    }
+
+    // The files are moved instead of copied, a world can be big
+    for entry in std::fs::read_dir(&aside)? {
+        let entry = entry?;
+        std::fs::rename(entry.path(), database.work_dir().join(entry.file_name()))?;
+    }
+    std::fs::remove_dir(&aside)?;
+    keep_folder_aside()?;
+
+    database.commit_all("Imported world")?;
+    with_retries(user, true, "Uploading", || database.push())
+}
+
+/// Snapshots kept by default when the history is pruned.
+pub const DEFAULT_KEPT_SNAPSHOTS: usize = 20;
+
+#[derive(Clone, Debug)]
+pub struct HistoryStats {
+    /// Bytes used by the local repo.
+    pub size: u64,
+    pub snapshots: usize,
+}
+
+/// Who has hosted and when, read from the history of the lock and the sessions of the world.
+pub fn hosting_log(user: &BackendUser) -> Result<Audit, Error> {
+    let database = open_git(user)?;
+    Ok(Audit::new(
+        &database.lock_history()?,
+        &database.snapshots()?,
+    ))
+}
 
-        Ok(())
+/// Writes the hosting log as a CSV file on the downloads folder.
+/// Returns where it has been written.
+pub fn export_hosting_log(user: &BackendUser) -> Result<PathBuf, Error> {
+    let csv = hosting_log(user)?.to_csv();
+    let date = chrono::Local::now().format("%Y-%m-%d");
+    let path = local_files::get_export_folder_path()?.join(format!("Hosting log {}.csv", date));
+    std::fs::write(&path, csv)?;
+    Ok(path)
+}
+
+pub fn history_stats(user: &BackendUser) -> Result<HistoryStats, Error> {
+    let database = open_git(user)?;
+
+    Ok(HistoryStats {
+        size: database.size()?,
+        snapshots: database.history_len()?,
+    })
+}
+
+/// Rewrites the history to keep the last `keep` snapshots and the tagged ones,
+/// then clones the repo again to free the space.
+/// Only the lock holder can do it, so nobody is uploading a session meanwhile.
+/// Returns the stats before and after.
+pub fn prune_history(
+    user: &BackendUser,
+    keep: usize,
+) -> Result<(HistoryStats, HistoryStats), Error> {
+    let database = open_git(user)?;
+
+    if database.fetch_lock()? != Some(user::id(git::get_username().ok())) {
+        return Err(Error::from_str(
+            "The server must be locked by you to prune the history",
+        ));
+    }
+
+    database.commit_all("Local changes before pruning the history")?;
+    match database.pull()? {
+        MergeStatus::Ok => database.push()?,
+        _ => {
+            return Err(Error::from_str(
+                "The local world is not in sync with the server",
+            ))
+        }
+    }
+
+    let before = HistoryStats {
+        size: database.size()?,
+        snapshots: database.history_len()?,
+    };
+
+    if database.prune_history(keep)? == 0 {
+        return Ok((before.clone(), before));
+    }
+    database.push_force()?;
+
+    let database = database.reclone()?;
+    let after = HistoryStats {
+        size: database.size()?,
+        snapshots: database.history_len()?,
+    };
+
+    Ok((before, after))
+}
+
+/// Backups of the local world, the newest first.
+pub fn list_backups() -> Result<Vec<Backup>, Error> {
+    backup::list(&local_files::get_app_folder_path()?)
+}
+
+/// Writes a backup over the local world, it will be uploaded on the next sync.
+pub fn restore_backup(user: &BackendUser, backup: &Path) -> Result<(), Error> {
+    let work_dir = local_files::get_app_folder_path()?;
+    backup::restore(&work_dir, backup, |done, total| {
+        user.status_count("Restoring backup", done, total)
     })
 }
+
+/// Writes the new settings and commits them with a message that lists the changes.
+/// They will be pushed on the next sync.
+pub fn save_server_settings(user: &BackendUser, settings: &ServerSettings) -> Result<(), Error> {
+    let database = open_store(user)?;
+
+    let previous = ServerSettings::load(database.work_dir())?;
+    settings.save(database.work_dir())?;
+    database.commit_all(&settings.describe_changes(&previous))
+}
+
+/// Enables or disables a plugin or mod and commits the change.
+/// It will be pushed on the next sync.
+pub fn set_plugin_enabled(user: &BackendUser, plugin: &Plugin, enabled: bool) -> Result<(), Error> {
+    let database = open_store(user)?;
+
+    plugins::set_plugin_enabled(database.work_dir(), plugin, enabled)?;
+
+    let action = if enabled { "Enable" } else { "Disable" };
+    let kind = match plugin.kind {
+        plugins::PluginKind::Plugin => "plugin",
+        plugins::PluginKind::Mod => "mod",
+    };
+    database.commit_all(&format!("{} {} {}", action, kind, plugin.name))
+}
@@ -0,0 +1,181 @@
+use crate::{backup, error::Error};
+use std::{
+    collections::BTreeSet,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const HOST_TRAILER: &str = "Host";
+const START_TRAILER: &str = "Session-Start";
+const END_TRAILER: &str = "Session-End";
+const DURATION_TRAILER: &str = "Duration";
+const PLAYERS_TRAILER: &str = "Players-Seen";
+const VERSION_TRAILER: &str = "Server-Version";
+const SIZE_DELTA_TRAILER: &str = "World-Size-Delta";
+
+/// Title of the snapshots that save a session.
+pub const HOSTING_TITLE: &str = "Hosting session";
+/// Title of the snapshots that save a session played without the lock.
+pub const OFFLINE_TITLE: &str = "Offline session";
+
+/// What happened while the server was open.
+/// It's written as trailers of the snapshot that saves the world after it:
+/// ```text
+/// Hosting session
+///
+/// Host: Alice
+/// Session-Start: 1666000000
+/// Session-End: 1666007200
+/// Duration: 7200
+/// Players-Seen: Alice, Bob
+/// Server-Version: 1.19.2
+/// World-Size-Delta: +1048576
+/// ```
+/// Times are unix seconds, the duration is in seconds and the size delta in bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Session {
+    /// Name of the host, see `user::display_name`.
+    /// `None` if it was unknown or the snapshot is older than the trailer.
+    pub host: Option<String>,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub players: BTreeSet<String>,
+    /// Read from the server console, `None` if the server stopped before saying it.
+    pub server_version: Option<String>,
+    /// Bytes that the world has grown, negative if it has shrunk.
+    pub world_size_delta: i64,
+}
+
+impl Session {
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap_or_default()
+    }
+
+    pub fn trailers(&self) -> String {
+        let players: Vec<&str> = self.players.iter().map(String::as_str).collect();
+        let mut trailers = match &self.host {
+            Some(host) => format!("{}: {}\n", HOST_TRAILER, host),
+            None => String::new(),
+        };
+        trailers += &format!(
+            "{}: {}\n{}: {}\n{}: {}\n{}: {}\n",
+            START_TRAILER,
+            unix_secs(self.start),
+            END_TRAILER,
+            unix_secs(self.end),
+            DURATION_TRAILER,
+            self.duration().as_secs(),
+            PLAYERS_TRAILER,
+            players.join(", "),
+        );
+        if let Some(version) = &self.server_version {
+            trailers += &format!("{}: {}\n", VERSION_TRAILER, version);
+        }
+        trailers += &format!("{}: {:+}\n", SIZE_DELTA_TRAILER, self.world_size_delta);
+        trailers
+    }
+
+    /// Reads the trailers of a snapshot message,
+    /// `None` if the snapshot doesn't save a session.
+    pub fn parse(message: &str) -> Option<Self> {
+        let mut host = None;
+        let mut start = None;
+        let mut end = None;
+        let mut players = BTreeSet::new();
+        let mut server_version = None;
+        let mut world_size_delta = 0;
+
+        for line in message.lines() {
+            let Some((key, value)) = line.split_once(": ") else {
+                continue;
+            };
+            let value = value.trim();
+            match key {
+                HOST_TRAILER => host = Some(value.to_string()),
+                START_TRAILER => start = value.parse().ok().map(from_unix_secs),
+                END_TRAILER => end = value.parse().ok().map(from_unix_secs),
+                PLAYERS_TRAILER => {
+                    players = value
+                        .split(", ")
+                        .filter(|player| !player.is_empty())
+                        .map(Into::into)
+                        .collect()
+                }
+                VERSION_TRAILER => server_version = Some(value.to_string()),
+                SIZE_DELTA_TRAILER => world_size_delta = value.parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+
+        Some(Self {
+            host,
+            start: start?,
+            end: end?,
+            players,
+            server_version,
+            world_size_delta,
+        })
+    }
+
+    /// The session is kept on a file until its snapshot is made.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, self.trailers())?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Bytes of the world files, without the state of the store.
+pub fn world_size(work_dir: &Path) -> Result<u64, Error> {
+    let mut size = 0;
+    for file in backup::world_files(work_dir)? {
+        size += std::fs::metadata(work_dir.join(file))?.len();
+    }
+    Ok(size)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+fn from_unix_secs(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailers_are_parsed_back() {
+        let session = Session {
+            host: Some("alice".into()),
+            start: from_unix_secs(1666000000),
+            end: from_unix_secs(1666007200),
+            players: BTreeSet::from(["Alex".to_string(), "Steve".to_string()]),
+            server_version: Some("1.19.2".into()),
+            world_size_delta: -1024,
+        };
+        let message = format!("{}\n\n{}", HOSTING_TITLE, session.trailers());
+        assert_eq!(Session::parse(&message), Some(session));
+    }
+
+    #[test]
+    fn snapshots_without_a_session_are_not_parsed() {
+        assert_eq!(Session::parse("Imported world"), None);
+        assert_eq!(Session::parse("Hosting session\n\nSession-Start: 1"), None);
+
+        let old = Session::parse("Hosting session\n\nSession-Start: 1\nSession-End: 2").unwrap();
+        assert_eq!(old.host, None);
+        assert!(old.players.is_empty());
+    }
+}
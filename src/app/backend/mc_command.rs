@@ -1,34 +1,122 @@
+use super::{BackendUser, GameRule, RconClient, RconConfig};
 use std::io::{self, Write};
 use std::process::ChildStdin;
+use std::sync::{
+    mpsc::{self, Sender},
+    Arc, Mutex,
+};
+use std::thread;
 
 #[derive(Debug)]
 pub struct CommandSender {
-    sender: ChildStdin,
+    sender: Arc<Mutex<ChildStdin>>,
+    commands: Sender<String>,
     cooldown: u8,
 }
 
 impl CommandSender {
-    pub fn new(stdin: ChildStdin) -> Self {
+    /// The commands given to `run` are sent from another thread,
+    /// it writes them and their output on `chat`.
+    pub fn new(
+        stdin: ChildStdin,
+        rcon_config: RconConfig,
+        chat: Arc<Mutex<String>>,
+        user: BackendUser,
+    ) -> Self {
+        let sender = Arc::new(Mutex::new(stdin));
+        let (commands, commands_recv) = mpsc::channel::<String>();
+
+        let console = sender.clone();
+        thread::spawn(move || {
+            let mut rcon = None;
+            // Ends when the sender is dropped, together with the scene
+            for cmd in commands_recv {
+                let output = run_command(&mut rcon, &rcon_config, &console, &cmd);
+
+                let Ok(mut chat) = chat.lock() else {
+                    return;
+                };
+                match output {
+                    Ok(Some(output)) if !output.is_empty() => {
+                        *chat += &output;
+                        *chat += "\n";
+                    }
+                    Ok(_) => {}
+                    Err(err) => *chat += &format!("{}\n", err),
+                }
+                user.request_repaint();
+            }
+        });
+
         Self {
-            sender: stdin,
+            sender,
+            commands,
             cooldown: 5,
         }
     }
+
     pub fn send(&mut self, cmd: &str) -> io::Result<()> {
-        writeln!(self.sender, "say {}", cmd)
+        self.write(&format!("say {}", cmd))
+    }
+
+    /// Queues the command, its output is added to the chat once the server answers.
+    pub fn run(&mut self, cmd: &str) -> io::Result<()> {
+        self.commands
+            .send(cmd.into())
+            .map_err(|_| io::Error::other("The server is closed"))
     }
 
     pub fn request_tps(&mut self) -> io::Result<()> {
         if self.cooldown <= 0 {
             self.cooldown = 4;
-            writeln!(self.sender, "tps")
+            self.write("tps")
         } else {
             self.cooldown -= 1;
             Ok(())
         }
     }
 
+    pub fn send_gamerule(&mut self, rule: &GameRule) -> io::Result<()> {
+        self.write(&format!("gamerule {} {}", rule.name, rule.value))
+    }
+
     pub fn send_stop(&mut self) -> io::Result<()> {
-        writeln!(self.sender, "stop")
+        self.write("stop")
+    }
+
+    fn write(&self, line: &str) -> io::Result<()> {
+        write_console(&self.sender, line)
     }
 }
+
+/// Runs the command through RCON and returns its output.
+/// Without RCON the command is written on the console and the output is unknown.
+fn run_command(
+    rcon: &mut Option<RconClient>,
+    rcon_config: &RconConfig,
+    console: &Mutex<ChildStdin>,
+    cmd: &str,
+) -> io::Result<Option<String>> {
+    if rcon.is_none() {
+        // The server only listens once it has started
+        *rcon = RconClient::connect(rcon_config).ok();
+    }
+
+    if let Some(client) = rcon.as_mut() {
+        match client.command(cmd) {
+            Ok(output) => return Ok(Some(output)),
+            // The connection is broken, the console still works
+            Err(_) => *rcon = None,
+        }
+    }
+
+    write_console(console, cmd)?;
+    Ok(None)
+}
+
+fn write_console(console: &Mutex<ChildStdin>, line: &str) -> io::Result<()> {
+    let mut console = console
+        .lock()
+        .map_err(|_| io::Error::other("Console lock poisoned"))?;
+    writeln!(console, "{}", line)
+}
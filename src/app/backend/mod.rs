@@ -1,19 +1,37 @@
+mod audit;
 mod backend_process;
 mod database;
 mod mc_command;
+mod plugins;
+mod rcon;
+mod server_settings;
 
 use crate::pull_channel::{pull_until_last, Received};
 
 use super::*;
+pub use audit::Audit;
 use backend_process::*;
+pub use database::host_status::SharedStatus;
 pub use mc_command::*;
-use std::{sync::mpsc, time::Duration};
+pub use plugins::{Plugin, PluginKind};
+pub use rcon::{RconClient, RconConfig};
+pub use server_settings::*;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
+};
 
 /// Used to send scene changes to the frontend
 #[derive(Clone)]
 pub struct BackendUser {
     update_scene: mpsc::Sender<Scene>,
     egui_ctx: egui::Context,
+    /// Set by the frontend to stop the current download.
+    cancel: Arc<AtomicBool>,
 }
 
 impl BackendUser {
@@ -36,21 +54,32 @@ impl BackendUser {
     fn request_repaint(&self) {
         self.egui_ctx.request_repaint();
     }
+
+    fn is_cancel_requested(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    fn clear_cancel_request(&self) {
+        self.cancel.store(false, Ordering::Relaxed);
+    }
 }
 
 pub struct Backend {
     scene_recv: mpsc::Receiver<Scene>,
     update_scene: mpsc::Sender<Scene>,
     action_sender: mpsc::Sender<Action>,
+    cancel: Arc<AtomicBool>,
 }
 
 impl Backend {
     pub fn new(egui_ctx: &egui::Context) -> Self {
         let (update_scene, scene_recv) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
 
         let backend_user = BackendUser {
             update_scene: update_scene.clone(),
             egui_ctx: egui_ctx.clone(),
+            cancel: cancel.clone(),
         };
 
         let action_sender = BackendProcess::start_thread(backend_user);
@@ -59,6 +88,7 @@ impl Backend {
             scene_recv,
             update_scene,
             action_sender,
+            cancel,
         }
     }
 
@@ -116,6 +146,285 @@ impl Backend {
         }
     }
 
+    /// Runs the server from the local copy without syncing it.
+    pub fn start_offline_server(&self, ram: u8) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Launching Minecraft Server offline...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self.action_sender.send(Action::OpenOfflineServer(ram)) {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    pub fn open_settings(&self) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Loading settings...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self.action_sender.send(Action::EditSettings) {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    pub fn save_settings(&self, settings: ServerSettings) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Saving settings...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self.action_sender.send(Action::SaveSettings(settings)) {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    pub fn open_plugins(&self) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Loading plugins...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self.action_sender.send(Action::ManagePlugins) {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    pub fn set_plugin_enabled(&self, plugin: Plugin, enabled: bool) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Updating plugins...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self
+            .action_sender
+            .send(Action::SetPluginEnabled(plugin, enabled))
+        {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    pub fn log_in(&self, username: String, token: String) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Logging in...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        let login = crate::git::Login { username, token };
+        if let Err(err) = self.action_sender.send(Action::SaveLogin(login)) {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    pub fn open_maintenance(&self) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Reading history...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self.action_sender.send(Action::Maintenance) {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    pub fn prune_history(&self, keep: usize) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Pruning history...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self.action_sender.send(Action::PruneHistory(keep)) {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    pub fn open_backups(&self) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Reading backups...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self.action_sender.send(Action::Backups) {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    pub fn restore_backup(&self, backup: PathBuf) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Restoring backup...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self.action_sender.send(Action::RestoreBackup(backup)) {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    /// Uploads the folder found on the place of the world as the world of a new server.
+    pub fn import_folder(&self) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Importing world...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self.action_sender.send(Action::ImportFolder) {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    /// Drops the local changes that conflict with the shared world, they are backed up first.
+    pub fn discard_local_changes(&self) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Discarding local changes...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self.action_sender.send(Action::DiscardLocalChanges) {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    pub fn keep_folder_aside(&self) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Loading...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self.action_sender.send(Action::KeepFolderAside) {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    pub fn open_hosting_log(&self) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Reading history...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self.action_sender.send(Action::HostingLog) {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    /// Writes the hosting log as a CSV file, for spreadsheets.
+    pub fn export_hosting_log(&self) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Exporting hosting log...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self.action_sender.send(Action::ExportHostingLog) {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    /// Leaves the settings, plugins, maintenance, backups or hosting log scene without saving.
+    pub fn close_menu(&self) {
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Loading...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+
+        if let Err(err) = self
+            .action_sender
+            .send(Action::Database(database::Action::Lock))
+        {
+            let err = format!("Error on send action to database: {}", err);
+            self.update_scene
+                .send(Scene::fatal_error(&err))
+                .expect("Could not update scene");
+        }
+    }
+
+    /// Stops the download that is running, the local copy is left as it was.
+    pub fn cancel_operation(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.update_scene
+            .send(Scene::Loading {
+                title: "Cancelling...".into(),
+                progress: 0.,
+            })
+            .expect("Could not update scene");
+    }
+
     /// Call this function to check for scene updates
     pub fn update_scene(&self) -> Option<Scene> {
         match pull_until_last(&self.scene_recv, Duration::ZERO) {
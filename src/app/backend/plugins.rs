@@ -0,0 +1,127 @@
+use crate::manifest::{self, Manifest};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+const DISABLED_EXTENSION: &str = ".disabled";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PluginKind {
+    Plugin,
+    Mod,
+}
+
+impl PluginKind {
+    pub const ALL: [PluginKind; 2] = [PluginKind::Plugin, PluginKind::Mod];
+
+    /// Folder, relative to the server path, where the jars of this kind live.
+    pub fn folder(&self) -> &'static str {
+        match self {
+            PluginKind::Plugin => "plugins",
+            PluginKind::Mod => "mods",
+        }
+    }
+}
+
+/// A jar inside `plugins/` or `mods/`.
+/// Disabled jars are renamed to `<name>.jar.disabled` so the server ignores them,
+/// but their signature stays on `<name>.jar.ed25519`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plugin {
+    pub kind: PluginKind,
+    /// File name of the enabled jar
+    pub name: String,
+    pub enabled: bool,
+    pub signed: bool,
+}
+
+impl Plugin {
+    /// Path relative to the server folder of the enabled jar.
+    pub fn local_path(&self) -> PathBuf {
+        Path::new(self.kind.folder()).join(&self.name)
+    }
+
+    fn disabled_local_path(&self) -> PathBuf {
+        Path::new(self.kind.folder()).join(format!("{}{}", self.name, DISABLED_EXTENSION))
+    }
+
+    fn current_local_path(&self) -> PathBuf {
+        if self.enabled {
+            self.local_path()
+        } else {
+            self.disabled_local_path()
+        }
+    }
+}
+
+/// Lists all the plugins and mods jars, enabled or not, sorted by name.
+pub fn list_plugins<P: AsRef<Path>>(server_path: P) -> io::Result<Vec<Plugin>> {
+    let mut plugins = vec![];
+    let manifest = Manifest::load_verified(server_path.as_ref());
+
+    for kind in PluginKind::ALL {
+        let folder = server_path.as_ref().join(kind.folder());
+        let entries = match std::fs::read_dir(&folder) {
+            Ok(entries) => entries,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let (name, enabled) = match file_name.strip_suffix(DISABLED_EXTENSION) {
+                Some(name) => (name.to_string(), false),
+                None => (file_name, true),
+            };
+
+            if !name.ends_with(".jar") {
+                continue;
+            }
+
+            let local_path = Path::new(kind.folder()).join(&name);
+            let problem = manifest::check_file(
+                server_path.as_ref(),
+                manifest.as_ref(),
+                &local_path,
+                &entry.path(),
+            );
+
+            plugins.push(Plugin {
+                kind,
+                name,
+                enabled,
+                signed: problem.is_none(),
+            });
+        }
+    }
+
+    plugins.sort_by_key(|plugin| plugin.name.to_lowercase());
+    Ok(plugins)
+}
+
+/// Renames the jar so the server loads it or ignores it.
+pub fn set_plugin_enabled<P: AsRef<Path>>(
+    server_path: P,
+    plugin: &Plugin,
+    enabled: bool,
+) -> io::Result<()> {
+    if plugin.enabled == enabled {
+        return Ok(());
+    }
+
+    let target = Plugin {
+        enabled,
+        ..plugin.clone()
+    };
+
+    std::fs::rename(
+        server_path.as_ref().join(plugin.current_local_path()),
+        server_path.as_ref().join(target.current_local_path()),
+    )
+}
@@ -0,0 +1,196 @@
+use super::{database::local_files, ServerProperties};
+use crate::verify_signature::to_hex;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    path::Path,
+    time::Duration,
+};
+
+pub const DEFAULT_RCON_PORT: u16 = 25575;
+
+const PACKET_LOGIN: i32 = 3;
+const PACKET_COMMAND: i32 = 2;
+const PACKET_RESPONSE: i32 = 0;
+/// The server answers the login with this id when the password is wrong.
+const AUTH_FAILED_ID: i32 = -1;
+/// Biggest packet that the server accepts.
+const MAX_PACKET_LEN: usize = 1460;
+/// Biggest packet that the server sends, long outputs are split in packets
+/// of up to 4096 bytes of body, plus the id, the kind and the two null bytes.
+const MAX_RESPONSE_LEN: usize = 4096 + 10;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug)]
+pub struct RconConfig {
+    pub port: u16,
+    pub password: String,
+}
+
+impl RconConfig {
+    /// Turns RCON on in `server.properties` for this launch.
+    /// The password is kept on this computer, see `local_files::get_rcon_password_path`,
+    /// and `disable` removes it from the world before it's uploaded.
+    pub fn enable(server_path: &Path) -> io::Result<Self> {
+        let mut properties = ServerProperties::load(server_path)?;
+
+        let port = properties
+            .get("rcon.port")
+            .and_then(|port| port.trim().parse().ok())
+            .unwrap_or(DEFAULT_RCON_PORT);
+
+        let config = Self {
+            port,
+            password: local_password()?,
+        };
+
+        properties.set("enable-rcon", "true");
+        properties.set("rcon.port", &port.to_string());
+        properties.set("rcon.password", &config.password);
+        properties.save(server_path)?;
+
+        Ok(config)
+    }
+
+    /// Removes the password from `server.properties`, RCON stays off until the next `enable`.
+    pub fn disable(server_path: &Path) -> io::Result<()> {
+        let mut properties = ServerProperties::load(server_path)?;
+        if properties
+            .get("rcon.password")
+            .unwrap_or_default()
+            .is_empty()
+        {
+            return Ok(());
+        }
+        properties.set("rcon.password", "");
+        properties.save(server_path)
+    }
+}
+
+/// The password of this computer, it's created the first time.
+fn local_password() -> io::Result<String> {
+    let path = local_files::get_rcon_password_path()?;
+    match std::fs::read_to_string(&path) {
+        Ok(password) if !password.trim().is_empty() => Ok(password.trim().into()),
+        Ok(_) => new_password(&path),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => new_password(&path),
+        Err(err) => Err(err),
+    }
+}
+
+fn new_password(path: &Path) -> io::Result<String> {
+    let mut bytes = [0; 16];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| io::Error::other("Could not generate rcon password"))?;
+    let password = to_hex(&bytes);
+    std::fs::write(path, &password)?;
+    Ok(password)
+}
+
+/// Client of the Minecraft RCON protocol.
+/// Every command gets its own response, unlike the console of the process.
+#[derive(Debug)]
+pub struct RconClient {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+impl RconClient {
+    /// Connects to the server running on this computer and logs in.
+    pub fn connect(config: &RconConfig) -> io::Result<Self> {
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, config.port));
+        let stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        let mut client = Self { stream, next_id: 1 };
+
+        let id = client.next_id();
+        client.write_packet(id, PACKET_LOGIN, &config.password)?;
+        let (response_id, _, _) = client.read_packet()?;
+
+        if response_id == AUTH_FAILED_ID {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Wrong rcon password",
+            ))
+        } else if response_id != id {
+            Err(invalid_data("Unexpected rcon login response"))
+        } else {
+            Ok(client)
+        }
+    }
+
+    /// Runs the command and returns its output.
+    pub fn command(&mut self, command: &str) -> io::Result<String> {
+        let id = self.next_id();
+        self.write_packet(id, PACKET_COMMAND, command)?;
+
+        // Long outputs are split in many packets with the same id.
+        // The server answers in order, so the response to an empty packet marks the end.
+        let end_id = self.next_id();
+        self.write_packet(end_id, PACKET_RESPONSE, "")?;
+
+        let mut output = String::new();
+        loop {
+            let (response_id, _, body) = self.read_packet()?;
+            if response_id == end_id {
+                return Ok(output);
+            } else if response_id == id {
+                output += &body;
+            }
+        }
+    }
+
+    fn next_id(&mut self) -> i32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+        id
+    }
+
+    fn write_packet(&mut self, id: i32, kind: i32, body: &str) -> io::Result<()> {
+        // id, kind, body and two null bytes
+        let len = 4 + 4 + body.len() + 2;
+        if len > MAX_PACKET_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The command is too long",
+            ));
+        }
+
+        let mut packet = Vec::with_capacity(4 + len);
+        packet.extend_from_slice(&(len as i32).to_le_bytes());
+        packet.extend_from_slice(&id.to_le_bytes());
+        packet.extend_from_slice(&kind.to_le_bytes());
+        packet.extend_from_slice(body.as_bytes());
+        packet.extend_from_slice(&[0, 0]);
+
+        self.stream.write_all(&packet)
+    }
+
+    fn read_packet(&mut self) -> io::Result<(i32, i32, String)> {
+        let mut len = [0; 4];
+        self.stream.read_exact(&mut len)?;
+        let len = i32::from_le_bytes(len);
+
+        // Checked before allocating, a broken length would allocate gigabytes
+        if len < 10 || len as usize > MAX_RESPONSE_LEN {
+            return Err(invalid_data("Invalid rcon packet length"));
+        }
+
+        let mut packet = vec![0; len as usize];
+        self.stream.read_exact(&mut packet)?;
+
+        let id = i32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]);
+        let kind = i32::from_le_bytes([packet[4], packet[5], packet[6], packet[7]]);
+        let body = String::from_utf8_lossy(&packet[8..packet.len() - 2]).into_owned();
+
+        Ok((id, kind, body))
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,491 @@
+use std::{fmt, io, path::Path};
+
+pub const SERVER_PROPERTIES_LOCAL_PATH: &str = "server.properties";
+pub const DEFAULT_SERVER_PORT: u16 = 25565;
+pub const GAMERULES_LOCAL_PATH: &str = "gamerules.txt";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Difficulty {
+    Peaceful,
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 4] = [
+        Difficulty::Peaceful,
+        Difficulty::Easy,
+        Difficulty::Normal,
+        Difficulty::Hard,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Difficulty::Peaceful => "peaceful",
+            Difficulty::Easy => "easy",
+            Difficulty::Normal => "normal",
+            Difficulty::Hard => "hard",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|d| d.as_str() == value)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GameMode {
+    Survival,
+    Creative,
+    Adventure,
+    Spectator,
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 4] = [
+        GameMode::Survival,
+        GameMode::Creative,
+        GameMode::Adventure,
+        GameMode::Spectator,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameMode::Survival => "survival",
+            GameMode::Creative => "creative",
+            GameMode::Adventure => "adventure",
+            GameMode::Spectator => "spectator",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.as_str() == value)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GameRuleValue {
+    Bool(bool),
+    Int(i32),
+}
+
+impl fmt::Display for GameRuleValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameRuleValue::Bool(value) => write!(f, "{}", value),
+            GameRuleValue::Int(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GameRule {
+    pub name: &'static str,
+    pub value: GameRuleValue,
+}
+
+/// Gamerules that can be edited from the app, with the vanilla defaults.
+/// The defaults are only shown, the world keeps its rules until one is saved.
+const DEFAULT_GAMERULES: [GameRule; 8] = [
+    GameRule {
+        name: "keepInventory",
+        value: GameRuleValue::Bool(false),
+    },
+    GameRule {
+        name: "mobGriefing",
+        value: GameRuleValue::Bool(true),
+    },
+    GameRule {
+        name: "doDaylightCycle",
+        value: GameRuleValue::Bool(true),
+    },
+    GameRule {
+        name: "doWeatherCycle",
+        value: GameRuleValue::Bool(true),
+    },
+    GameRule {
+        name: "doFireTick",
+        value: GameRuleValue::Bool(true),
+    },
+    GameRule {
+        name: "doInsomnia",
+        value: GameRuleValue::Bool(true),
+    },
+    GameRule {
+        name: "playersSleepingPercentage",
+        value: GameRuleValue::Int(100),
+    },
+    GameRule {
+        name: "randomTickSpeed",
+        value: GameRuleValue::Int(3),
+    },
+];
+
+/// The editable part of `server.properties` and `gamerules.txt`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerSettings {
+    pub motd: String,
+    pub difficulty: Difficulty,
+    pub gamemode: GameMode,
+    pub max_players: u32,
+    pub view_distance: u32,
+    pub simulation_distance: u32,
+    pub spawn_protection: u32,
+    pub white_list: bool,
+    pub pvp: bool,
+    pub allow_flight: bool,
+    pub gamerules: Vec<GameRule>,
+    properties: ServerProperties,
+    /// Rules of `gamerules.txt`, the other ones are not written unless they are changed.
+    saved_gamerules: Vec<GameRule>,
+}
+
+impl ServerSettings {
+    pub fn load<P: AsRef<Path>>(server_path: P) -> io::Result<Self> {
+        let properties = ServerProperties::load(server_path.as_ref())?;
+        let saved_gamerules = load_gamerules(server_path.as_ref())?;
+        let gamerules = DEFAULT_GAMERULES
+            .iter()
+            .map(|default| {
+                saved_gamerules
+                    .iter()
+                    .find(|rule| rule.name == default.name)
+                    .unwrap_or(default)
+            })
+            .copied()
+            .collect();
+
+        let get_num = |key: &str, default: u32| {
+            properties
+                .get(key)
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        let get_bool = |key: &str, default: bool| {
+            properties
+                .get(key)
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+
+        Ok(Self {
+            motd: properties
+                .get("motd")
+                .unwrap_or_else(|| "A Minecraft Server".into()),
+            difficulty: properties
+                .get("difficulty")
+                .and_then(|value| Difficulty::parse(&value))
+                .unwrap_or(Difficulty::Easy),
+            gamemode: properties
+                .get("gamemode")
+                .and_then(|value| GameMode::parse(&value))
+                .unwrap_or(GameMode::Survival),
+            max_players: get_num("max-players", 20),
+            view_distance: get_num("view-distance", 10),
+            simulation_distance: get_num("simulation-distance", 10),
+            spawn_protection: get_num("spawn-protection", 16),
+            white_list: get_bool("white-list", false),
+            pvp: get_bool("pvp", true),
+            allow_flight: get_bool("allow-flight", false),
+            gamerules,
+            properties,
+            saved_gamerules,
+        })
+    }
+
+    /// Returns a human readable error if some field has an invalid value.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.motd.contains('\n') || self.motd.contains('\r') {
+            return Err("The MOTD must be a single line.".into());
+        }
+        if self.motd.chars().count() > 59 {
+            return Err("The MOTD can't be longer than 59 characters.".into());
+        }
+        if !(1..=1000).contains(&self.max_players) {
+            return Err("Max players must be between 1 and 1000.".into());
+        }
+        if !(3..=32).contains(&self.view_distance) {
+            return Err("View distance must be between 3 and 32.".into());
+        }
+        if !(3..=32).contains(&self.simulation_distance) {
+            return Err("Simulation distance must be between 3 and 32.".into());
+        }
+        if self.spawn_protection > 256 {
+            return Err("Spawn protection can't be bigger than 256.".into());
+        }
+        for rule in &self.gamerules {
+            if let GameRuleValue::Int(value) = rule.value {
+                if value < 0 {
+                    return Err(format!("The gamerule {} can't be negative.", rule.name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, server_path: P) -> io::Result<()> {
+        if let Err(msg) = self.validate() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+
+        let mut properties = self.properties.clone();
+        for (key, value) in self.property_values() {
+            properties.set(key, &value);
+        }
+        properties.save(server_path.as_ref())?;
+
+        let gamerules: String = self
+            .gamerules
+            .iter()
+            .filter(|rule| {
+                !DEFAULT_GAMERULES.contains(rule)
+                    || self
+                        .saved_gamerules
+                        .iter()
+                        .any(|saved| saved.name == rule.name)
+            })
+            .map(|rule| format!("{}={}\n", rule.name, rule.value))
+            .collect();
+        std::fs::write(server_path.as_ref().join(GAMERULES_LOCAL_PATH), gamerules)
+    }
+
+    /// Describes the changes from `previous` in a way that fits in a commit message.
+    pub fn describe_changes(&self, previous: &ServerSettings) -> String {
+        let mut changes: Vec<String> = previous
+            .property_values()
+            .into_iter()
+            .zip(self.property_values())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((key, old), (_, new))| format!("{}: {} -> {}", key, old, new))
+            .collect();
+
+        for (old, new) in previous.gamerules.iter().zip(&self.gamerules) {
+            if old.value != new.value {
+                changes.push(format!("{}: {} -> {}", new.name, old.value, new.value));
+            }
+        }
+
+        if changes.is_empty() {
+            "Update server settings".into()
+        } else {
+            format!("Update server settings\n\n{}", changes.join("\n"))
+        }
+    }
+
+    fn property_values(&self) -> [(&'static str, String); 10] {
+        [
+            ("motd", self.motd.clone()),
+            ("difficulty", self.difficulty.as_str().into()),
+            ("gamemode", self.gamemode.as_str().into()),
+            ("max-players", self.max_players.to_string()),
+            ("view-distance", self.view_distance.to_string()),
+            ("simulation-distance", self.simulation_distance.to_string()),
+            ("spawn-protection", self.spawn_protection.to_string()),
+            ("white-list", self.white_list.to_string()),
+            ("pvp", self.pvp.to_string()),
+            ("allow-flight", self.allow_flight.to_string()),
+        ]
+    }
+}
+
+/// Reads the rules saved on `gamerules.txt`, an empty list if it doesn't exist.
+/// Unknown or invalid rules are skipped, the world keeps the value it has.
+pub fn load_gamerules<P: AsRef<Path>>(server_path: P) -> io::Result<Vec<GameRule>> {
+    let content = match std::fs::read_to_string(server_path.as_ref().join(GAMERULES_LOCAL_PATH)) {
+        Ok(content) => content,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+
+    let mut gamerules = vec![];
+    for line in content.lines() {
+        let Some((name, value)) = line.split_once("=") else {
+            continue;
+        };
+        let Some(default) = DEFAULT_GAMERULES
+            .iter()
+            .find(|rule| rule.name == name.trim())
+        else {
+            continue;
+        };
+        let value = value.trim();
+        let value = match default.value {
+            GameRuleValue::Bool(_) => value.parse().ok().map(GameRuleValue::Bool),
+            GameRuleValue::Int(_) => value.parse().ok().map(GameRuleValue::Int),
+        };
+        if let Some(value) = value {
+            gamerules.push(GameRule {
+                name: default.name,
+                value,
+            });
+        }
+    }
+    Ok(gamerules)
+}
+
+/// A `server.properties` file that keeps comments, ordering and unknown keys untouched.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ServerProperties {
+    lines: Vec<String>,
+}
+
+impl ServerProperties {
+    pub fn load(server_path: &Path) -> io::Result<Self> {
+        match std::fs::read_to_string(server_path.join(SERVER_PROPERTIES_LOCAL_PATH)) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn parse(content: &str) -> Self {
+        Self {
+            lines: content.lines().map(String::from).collect(),
+        }
+    }
+
+    pub fn save(&self, server_path: &Path) -> io::Result<()> {
+        let mut content = self.lines.join("\n");
+        content.push('\n');
+        std::fs::write(server_path.join(SERVER_PROPERTIES_LOCAL_PATH), content)
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.lines
+            .iter()
+            .find_map(|line| match split_property(line) {
+                Some((line_key, value)) if line_key == key => Some(unescape(value)),
+                _ => None,
+            })
+    }
+
+    pub fn server_port(&self) -> u16 {
+        self.get("server-port")
+            .and_then(|port| port.trim().parse().ok())
+            .unwrap_or(DEFAULT_SERVER_PORT)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        let new_line = format!("{}={}", key, escape(value));
+        let line = self
+            .lines
+            .iter_mut()
+            .find(|line| matches!(split_property(line), Some((line_key, _)) if line_key == key));
+
+        match line {
+            Some(line) => *line = new_line,
+            None => self.lines.push(new_line),
+        }
+    }
+}
+
+fn split_property(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+    if line.starts_with('#') || line.starts_with('!') {
+        return None;
+    }
+    line.split_once('=').map(|(key, value)| (key.trim(), value))
+}
+
+/// Undoes the escaping that java applies to `.properties` values.
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('u') => {
+                let code: String = chars.by_ref().take(4).collect();
+                match u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                    Some(c) => result.push(c),
+                    None => result.push_str(&code),
+                }
+            }
+            Some(c) => result.push(c),
+            None => {}
+        }
+    }
+    result
+}
+
+fn escape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '\r' => result.push_str("\\r"),
+            '=' | ':' | '#' | '!' => {
+                result.push('\\');
+                result.push(c);
+            }
+            c if c.is_ascii() => result.push(c),
+            c => {
+                let mut buf = [0u16; 2];
+                for unit in c.encode_utf16(&mut buf) {
+                    result.push_str(&format!("\\u{:04X}", unit));
+                }
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn only_saved_gamerules_are_loaded() {
+        let dir = server_dir("gamerules-missing");
+        assert_eq!(load_gamerules(&dir).unwrap(), vec![]);
+
+        std::fs::write(
+            dir.join(GAMERULES_LOCAL_PATH),
+            "keepInventory=true\nrandomTickSpeed=fast\nunknownRule=1\n",
+        )
+        .unwrap();
+        let keep_inventory = GameRule {
+            name: "keepInventory",
+            value: GameRuleValue::Bool(true),
+        };
+        assert_eq!(load_gamerules(&dir).unwrap(), vec![keep_inventory]);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn unchanged_defaults_are_not_saved() {
+        let dir = server_dir("gamerules-save");
+        std::fs::write(dir.join(GAMERULES_LOCAL_PATH), "doFireTick=true\n").unwrap();
+
+        let mut settings = ServerSettings::load(&dir).unwrap();
+        settings.motd = "Changed".into();
+        for rule in settings.gamerules.iter_mut() {
+            if rule.name == "mobGriefing" {
+                rule.value = GameRuleValue::Bool(false);
+            }
+        }
+        settings.save(&dir).unwrap();
+
+        let saved = std::fs::read_to_string(dir.join(GAMERULES_LOCAL_PATH)).unwrap();
+        assert_eq!(saved, "mobGriefing=false\ndoFireTick=true\n");
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
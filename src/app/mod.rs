@@ -2,8 +2,15 @@ mod backend;
 mod local_storage;
 mod user;
 
-use self::backend::{Backend, CommandSender};
+use self::backend::{
+    Audit, Backend, CommandSender, Difficulty, GameMode, GameRuleValue, Plugin, PluginKind,
+    ServerSettings, SharedStatus,
+};
 use super::*;
+use crate::{
+    backup::Backup, git::TransferProgress, port_mapping::PortMappingStatus,
+    reachability::Reachability,
+};
 use eframe::egui::style::Margin;
 use std::sync::{Arc, Mutex};
 
@@ -12,22 +19,78 @@ pub enum Scene {
     Unlocked,
     SomeoneLocked {
         host_id: String,
+        /// Refreshed by the backend, see `StatusRefresher`.
+        status: SharedStatus,
     },
     SelfLocked,
+    Settings {
+        settings: ServerSettings,
+        error: Option<String>,
+    },
+    Plugins {
+        plugins: Vec<Plugin>,
+    },
+    Maintenance {
+        /// Bytes used by the local world repository.
+        size: u64,
+        snapshots: usize,
+        /// Snapshots to keep when pruning.
+        keep: usize,
+        result: Option<String>,
+    },
+    /// Copies of the world made before it was reset or downloaded again.
+    Backups {
+        backups: Vec<Backup>,
+        result: Option<String>,
+    },
+    /// Who has hosted and when.
+    HostingLog {
+        audit: Audit,
+        result: Option<String>,
+    },
+    Login {
+        username: String,
+        token: String,
+        error: Option<String>,
+    },
     Hosting {
         chat: Arc<Mutex<String>>,
         players: Arc<Mutex<Vec<String>>>,
         tps: Arc<Mutex<f32>>,
+        server_port: u16,
+        port_mapping: Arc<Mutex<PortMappingStatus>>,
+        reachability: Arc<Mutex<Reachability>>,
         command: String,
         command_sender: CommandSender,
+        /// Running from the local copy, the changes are not uploaded until the next sync.
+        offline: bool,
     },
     Loading {
         title: String,
         progress: f32,
     },
+    /// Loading with the details of a download or an upload.
+    Transfer {
+        title: String,
+        progress: TransferProgress,
+    },
     RepoConflicts {
         conflicts_count: usize,
     },
+    /// The place of the world had files that are not a local copy, they have been moved to `path`.
+    SetAsideFolder {
+        path: String,
+        error: Option<String>,
+    },
+    /// A download was stopped by the user before changing the local copy.
+    Cancelled,
+    /// The origin could not be reached, it keeps retrying.
+    ConnectionFailed {
+        details: String,
+        /// There are local changes waiting to be uploaded,
+        /// from an offline session or from a failed upload.
+        unsynced: bool,
+    },
     Error {
         title: String,
         message: String,
@@ -80,7 +143,12 @@ impl App {
             Scene::RepoConflicts { .. }
             | Scene::Error { .. }
             | Scene::Unlocked
-            | Scene::SomeoneLocked { .. } => true,
+            | Scene::SomeoneLocked { .. }
+            | Scene::Cancelled
+            | Scene::SetAsideFolder { .. }
+            | Scene::Login { .. } => true,
+            // The changes would stay on this computer, it's confirmed on the scene
+            Scene::ConnectionFailed { unsynced, .. } => !unsynced,
             _ => false,
         }
     }
@@ -119,6 +187,15 @@ impl eframe::App for App {
 
                 let size = match &self.scene {
                     Scene::Hosting { .. } => vec2(600., auto_height),
+                    Scene::Settings { .. }
+                    | Scene::Plugins { .. }
+                    | Scene::Login { .. }
+                    | Scene::ConnectionFailed { .. }
+                    | Scene::SetAsideFolder { .. }
+                    | Scene::Transfer { .. }
+                    | Scene::Maintenance { .. }
+                    | Scene::Backups { .. } => vec2(400., auto_height),
+                    Scene::HostingLog { .. } => vec2(500., auto_height),
                     // Scene::RepoConflicts { .. } => vec2(740., auto_height),
                     Scene::Error { .. } => vec2(400., auto_height),
                     _ => vec2(300., auto_height),
@@ -192,10 +269,84 @@ impl App {
                     self.backend.lock_server();
                 }
             }
-            Scene::SomeoneLocked { host_id } => {
+            Scene::SomeoneLocked { host_id, status } => {
                 ui.heading("Server Locked");
                 ui.separator();
                 ui.label(format!("Host: {}", user::display(host_id)));
+
+                let Ok(status) = status.lock() else {
+                    self.scene = Scene::fatal_error("Backend panicked while holding a lock.");
+                    ui.ctx().request_repaint();
+                    return;
+                };
+                match &*status {
+                    None => {
+                        ui.label("Checking the server...");
+                    }
+                    Some(Ok(status)) => {
+                        ui.label(format!(
+                            "Online ({} ms), version {}",
+                            status.latency.as_millis(),
+                            status.version
+                        ));
+                        ui.monospace(&status.motd);
+                        ui.label(format!(
+                            "Players: {}/{}",
+                            status.online_players, status.max_players
+                        ));
+                        ui.indent("sample players indent", |ui| {
+                            for player in &status.sample_players {
+                                ui.monospace(player);
+                            }
+                        });
+                    }
+                    Some(Err(reason)) => {
+                        ui.label("The server is not online yet.");
+                        ui.indent("server status details", |ui| ui.small(reason));
+                    }
+                }
+            }
+            Scene::ConnectionFailed { details, unsynced } => {
+                ui.heading("Can't connect");
+                ui.separator();
+                ui.label("The server could not be reached. Retrying...");
+                ui.indent("connection details", |ui| ui.small(&*details));
+
+                if *unsynced {
+                    ui.colored_label(
+                        Color32::from_rgb(220, 140, 40),
+                        "Your last changes have not been uploaded yet.\n\
+                        Keep the app open until they are, or open it again later.",
+                    );
+
+                    if self.try_close {
+                        ui.separator();
+                        ui.label(
+                            "If you close the app now, the changes will stay on this computer \
+                            and nobody else will have them until you open it again.",
+                        );
+                        if ui.button("Close Anyway").clicked() {
+                            win_frame.close();
+                        }
+                    }
+                }
+
+                ui.separator();
+                ui.label(
+                    "You can play offline on the last synced world. \
+                    It will be merged when the connection is back, \
+                    if someone else plays meanwhile you will have conflicts.",
+                );
+                ui.horizontal(|ui| {
+                    ui.label(format!("Ram: {}GB", self.ram));
+                    let slider = Slider::new(&mut self.ram, 1..=6).show_value(false);
+                    if ui.add(slider).changed() {
+                        local_storage::set!(win_frame, "ram", self.ram);
+                    }
+                });
+                if ui.button("Play Offline").clicked() {
+                    self.backend.start_offline_server(self.ram);
+                }
             }
             Scene::SelfLocked => {
                 ui.heading("You have the Power");
@@ -208,19 +359,391 @@ impl App {
                     }
                 });
 
-                if ui.button("Start Server").clicked() {
-                    self.backend.start_server(self.ram);
+                ui.horizontal(|ui| {
+                    if ui.button("Start Server").clicked() {
+                        self.backend.start_server(self.ram);
+                    }
+                    if ui.button("Settings").clicked() {
+                        self.backend.open_settings();
+                    }
+                    if ui.button("Plugins").clicked() {
+                        self.backend.open_plugins();
+                    }
+                    if ui.button("Maintenance").clicked() {
+                        self.backend.open_maintenance();
+                    }
+                    if ui.button("Backups").clicked() {
+                        self.backend.open_backups();
+                    }
+                    if ui.button("Hosting Log").clicked() {
+                        self.backend.open_hosting_log();
+                    }
+                });
+            }
+            Scene::Plugins { plugins } => {
+                ui.heading("Plugins & Mods");
+                ui.separator();
+
+                for kind in PluginKind::ALL {
+                    let kind_plugins: Vec<&Plugin> = plugins
+                        .iter()
+                        .filter(|plugin| plugin.kind == kind)
+                        .collect();
+                    if kind_plugins.is_empty() {
+                        continue;
+                    }
+
+                    ui.label(match kind {
+                        PluginKind::Plugin => "Plugins:",
+                        PluginKind::Mod => "Mods:",
+                    });
+                    ui.indent(kind.folder(), |ui| {
+                        for plugin in kind_plugins {
+                            ui.horizontal(|ui| {
+                                let mut enabled = plugin.enabled;
+                                if ui.checkbox(&mut enabled, &plugin.name).changed() {
+                                    self.backend.set_plugin_enabled(plugin.clone(), enabled);
+                                }
+                                if !plugin.signed {
+                                    ui.colored_label(Color32::from_rgb(220, 60, 60), "Unsigned");
+                                }
+                            });
+                        }
+                    });
+                }
+
+                if plugins.is_empty() {
+                    ui.label("There are no plugins or mods installed.");
+                } else if plugins
+                    .iter()
+                    .any(|plugin| plugin.enabled && !plugin.signed)
+                {
+                    ui.small("The server will not start while an unsigned jar is enabled.");
+                }
+
+                if ui.button("Back").clicked() {
+                    self.backend.close_menu();
                 }
             }
+            Scene::Maintenance {
+                size,
+                snapshots,
+                keep,
+                result,
+            } => {
+                ui.heading("Maintenance");
+                ui.separator();
+                ui.label(format!(
+                    "The world repository has {} snapshots and uses {}.",
+                    snapshots,
+                    format_size(*size)
+                ));
+
+                ui.horizontal(|ui| {
+                    ui.label("Snapshots to keep");
+                    ui.add(DragValue::new(keep).clamp_range(1..=1000));
+                });
+                ui.small(
+                    "Older snapshots are removed, except the tagged ones.\n\
+                    Everyone will download the world again on the next sync.",
+                );
+
+                if let Some(result) = result {
+                    ui.label(&*result);
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Prune History").clicked() {
+                        self.backend.prune_history(*keep);
+                    }
+                    if ui.button("Back").clicked() {
+                        self.backend.close_menu();
+                    }
+                });
+            }
+            Scene::Backups { backups, result } => {
+                ui.heading("Backups");
+                ui.separator();
+                ui.label(
+                    "The world is backed up before it's reset or downloaded again. \
+                    Restoring a backup writes its files over the world.",
+                );
+
+                if backups.is_empty() {
+                    ui.label("There are no backups yet.");
+                }
+                Grid::new("backups grid").num_columns(4).show(ui, |ui| {
+                    for backup in backups.iter() {
+                        let age = backup.created.elapsed().unwrap_or_default();
+                        ui.label(format!("{} ago", format_duration(age)));
+                        ui.label(&backup.reason);
+                        ui.label(format_size(backup.size));
+                        if backup.is_history() {
+                            ui.small("History, extract it by hand");
+                        } else if ui.button("Restore").clicked() {
+                            self.backend.restore_backup(backup.path.clone());
+                        }
+                        ui.end_row();
+                    }
+                });
+
+                if let Some(result) = result {
+                    ui.label(&*result);
+                }
+
+                if ui.button("Back").clicked() {
+                    self.backend.close_menu();
+                }
+            }
+            Scene::HostingLog { audit, result } => {
+                ui.heading("Hosting Log");
+                ui.separator();
+
+                ui.label("Most hosted this month:");
+                if audit.leaderboard.is_empty() {
+                    ui.label("Nobody has hosted this month.");
+                }
+                Grid::new("leaderboard grid").num_columns(3).show(ui, |ui| {
+                    for (place, rank) in audit.leaderboard.iter().enumerate() {
+                        ui.label(format!("{}. {}", place + 1, rank.host));
+                        ui.label(format_duration(rank.hosted));
+                        ui.label(format!("{} times", rank.periods));
+                        ui.end_row();
+                    }
+                });
+
+                ui.separator();
+                ScrollArea::vertical().max_height(300.).show(ui, |ui| {
+                    Grid::new("hosting log grid").num_columns(4).show(ui, |ui| {
+                        for period in audit.periods.iter() {
+                            ui.label(&period.host);
+                            ui.label(format_date(period.start));
+                            match period.end {
+                                Some(_) => ui.label(format_duration(period.duration())),
+                                None => ui.label("Hosting now"),
+                            };
+                            let players: Vec<&str> = period.players().into_iter().collect();
+                            if period.offline {
+                                ui.small("Offline");
+                            } else {
+                                ui.small(players.join(", "));
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+
+                if let Some(result) = result {
+                    ui.label(&*result);
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Export CSV").clicked() {
+                        self.backend.export_hosting_log();
+                    }
+                    if ui.button("Back").clicked() {
+                        self.backend.close_menu();
+                    }
+                });
+            }
+            Scene::Login {
+                username,
+                token,
+                error,
+            } => {
+                ui.heading("Log in");
+                ui.separator();
+                ui.label(
+                    "Every member pushes the world with their own GitHub account.\n\
+                    Use an access token with permission to push to the server repository.",
+                );
+                ui.small(
+                    "If git already has your credentials (credential helper or SSH keys) \
+                    they are used instead.",
+                );
+
+                Grid::new("login grid").num_columns(2).show(ui, |ui| {
+                    ui.label("GitHub user");
+                    ui.add(TextEdit::singleline(username).desired_width(220.));
+                    ui.end_row();
+
+                    ui.label("Access token");
+                    ui.add(
+                        TextEdit::singleline(token)
+                            .password(true)
+                            .desired_width(220.),
+                    );
+                    ui.end_row();
+                });
+
+                if let Some(error) = error {
+                    ui.colored_label(Color32::from_rgb(220, 60, 60), &*error);
+                }
+
+                if ui.button("Log in").clicked() {
+                    if username.trim().is_empty() || token.trim().is_empty() {
+                        *error = Some("The user and the token are required.".into());
+                    } else {
+                        self.backend
+                            .log_in(username.trim().into(), token.trim().into());
+                    }
+                }
+            }
+            Scene::Settings { settings, error } => {
+                ui.heading("Server Settings");
+                ui.separator();
+
+                Grid::new("server settings grid")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label("MOTD");
+                        ui.add(TextEdit::singleline(&mut settings.motd).desired_width(220.));
+                        ui.end_row();
+
+                        ui.label("Difficulty");
+                        ComboBox::from_id_source("difficulty")
+                            .selected_text(settings.difficulty.as_str())
+                            .show_ui(ui, |ui| {
+                                for difficulty in Difficulty::ALL {
+                                    ui.selectable_value(
+                                        &mut settings.difficulty,
+                                        difficulty,
+                                        difficulty.as_str(),
+                                    );
+                                }
+                            });
+                        ui.end_row();
+
+                        ui.label("Game mode");
+                        ComboBox::from_id_source("gamemode")
+                            .selected_text(settings.gamemode.as_str())
+                            .show_ui(ui, |ui| {
+                                for gamemode in GameMode::ALL {
+                                    ui.selectable_value(
+                                        &mut settings.gamemode,
+                                        gamemode,
+                                        gamemode.as_str(),
+                                    );
+                                }
+                            });
+                        ui.end_row();
+
+                        ui.label("Max players");
+                        ui.add(DragValue::new(&mut settings.max_players).clamp_range(1..=1000));
+                        ui.end_row();
+
+                        ui.label("View distance");
+                        ui.add(Slider::new(&mut settings.view_distance, 3..=32));
+                        ui.end_row();
+
+                        ui.label("Simulation distance");
+                        ui.add(Slider::new(&mut settings.simulation_distance, 3..=32));
+                        ui.end_row();
+
+                        ui.label("Spawn protection");
+                        ui.add(DragValue::new(&mut settings.spawn_protection).clamp_range(0..=256));
+                        ui.end_row();
+                    });
+
+                ui.checkbox(&mut settings.white_list, "Whitelist");
+                ui.checkbox(&mut settings.pvp, "PvP");
+                ui.checkbox(&mut settings.allow_flight, "Allow flight");
+
+                ui.collapsing("Gamerules", |ui| {
+                    for rule in &mut settings.gamerules {
+                        match &mut rule.value {
+                            GameRuleValue::Bool(value) => {
+                                ui.checkbox(value, rule.name);
+                            }
+                            GameRuleValue::Int(value) => {
+                                ui.horizontal(|ui| {
+                                    ui.add(DragValue::new(value).clamp_range(0..=i32::MAX));
+                                    ui.label(rule.name);
+                                });
+                            }
+                        }
+                    }
+                });
+
+                if let Some(error) = error {
+                    ui.colored_label(Color32::from_rgb(220, 60, 60), &*error);
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        match settings.validate() {
+                            Ok(()) => self.backend.save_settings(settings.clone()),
+                            Err(msg) => *error = Some(msg),
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.backend.close_menu();
+                    }
+                });
+            }
             Scene::Hosting {
                 chat,
                 players,
                 tps,
+                server_port,
+                port_mapping,
+                reachability,
                 command,
                 command_sender,
+                offline,
             } => {
                 ui.heading("You are hosting");
 
+                if *offline {
+                    ui.colored_label(
+                        Color32::from_rgb(220, 140, 40),
+                        "Offline session: the world is not synced.\n\
+                        The changes will be uploaded on the next online launch.",
+                    );
+                }
+
+                if let Ok(port_mapping) = port_mapping.lock() {
+                    match &*port_mapping {
+                        PortMappingStatus::Requesting => {
+                            ui.label("Requesting port forwarding to the router...");
+                        }
+                        PortMappingStatus::Mapped { method } => {
+                            ui.label(format!("Port {} forwarded with {}.", server_port, method));
+                        }
+                        PortMappingStatus::Unavailable(reason) => {
+                            ui.label("Automatic port forwarding is not available.");
+                            ui.indent("port mapping details", |ui| ui.small(reason));
+                        }
+                    }
+                }
+
+                if let Ok(reachability) = reachability.lock() {
+                    match &*reachability {
+                        Reachability::Checking => {
+                            ui.label(format!("Checking if port {} is open...", server_port));
+                        }
+                        Reachability::Reachable => {
+                            ui.label(format!(
+                                "Port {} is open, friends can connect.",
+                                server_port
+                            ));
+                        }
+                        Reachability::Unreachable => {
+                            ui.colored_label(
+                                Color32::from_rgb(220, 60, 60),
+                                format!(
+                                    "Port {} is not reachable from internet, friends can't connect.\n\
+                                    Forward it on your router to this computer.",
+                                    server_port
+                                ),
+                            );
+                        }
+                        Reachability::Unknown(reason) => {
+                            ui.label(format!("Could not check port {}: {}", server_port, reason));
+                        }
+                    }
+                }
+
                 ui.separator();
 
                 let tps = {
@@ -232,7 +755,7 @@ impl App {
                     *tps
                 };
 
-                let Ok(chat) = chat.lock() else {
+                let Ok(mut chat) = chat.lock() else {
                     self.scene = Scene::fatal_error("Backend panicked while holding a lock.");
                     ui.ctx().request_repaint();
                     return;
@@ -255,7 +778,15 @@ impl App {
                                 .font(TextStyle::Monospace)
                                 .show(ui);
                             if input.response.lost_focus() && ui.input().key_down(Key::Enter) {
-                                let _ = command_sender.send(command);
+                                // Commands start with a slash like in the game chat
+                                if let Some(cmd) = command.strip_prefix('/') {
+                                    *chat += &format!("> /{}\n", cmd);
+                                    if let Err(err) = command_sender.run(cmd) {
+                                        *chat += &format!("{}\n", err);
+                                    }
+                                } else {
+                                    let _ = command_sender.send(command);
+                                }
                                 *command = "".into();
                                 input.response.request_focus();
                             }
@@ -303,6 +834,69 @@ impl App {
                     });
                 }
             }
+            Scene::Transfer { title, progress } => {
+                ui.heading(title.as_str());
+                let fraction = progress.fraction();
+                ui.horizontal(|ui| {
+                    ui.label(format!("{:.1}%", fraction * 100.));
+                    ui.add(ProgressBar::new(fraction));
+                });
+
+                ui.label(format!(
+                    "{} of {} {}",
+                    progress.done, progress.total, progress.unit
+                ));
+                if let Some(bytes) = progress.bytes {
+                    let mut transferred = match progress.total_bytes {
+                        Some(total) => format!("{} of {}", format_size(bytes), format_size(total)),
+                        None => format_size(bytes),
+                    };
+                    if let Some(speed) = progress.bytes_per_second() {
+                        transferred += &format!(" at {}/s", format_size(speed as u64));
+                    }
+                    ui.label(transferred);
+                }
+                if let Some(eta) = progress.eta() {
+                    ui.label(format!("About {} left", format_duration(eta)));
+                }
+                if progress.cancellable && ui.button("Cancel").clicked() {
+                    self.backend.cancel_operation();
+                }
+            }
+            Scene::SetAsideFolder { path, error } => {
+                ui.heading("Existing folder found");
+                ui.separator();
+                ui.label(
+                    "The server folder had files that are not a shared world. \
+                    Nothing has been deleted, they have been moved to:",
+                );
+                ui.monospace(&*path);
+                ui.label(
+                    "If it's a world for a new shared server, it can be uploaded as its world. \
+                    Otherwise it's kept in that folder.",
+                );
+
+                if let Some(error) = error {
+                    ui.colored_label(Color32::from_rgb(220, 140, 40), &*error);
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Import as World").clicked() {
+                        self.backend.import_folder();
+                    }
+                    if ui.button("Keep Aside").clicked() {
+                        self.backend.keep_folder_aside();
+                    }
+                });
+            }
+            Scene::Cancelled => {
+                ui.heading("Cancelled");
+                ui.separator();
+                ui.label("Nothing has been changed.");
+                if ui.button("Retry").clicked() {
+                    self.backend.unlock_server();
+                }
+            }
             Scene::RepoConflicts { conflicts_count } => {
                 if *conflicts_count == 0 {
                     ui.heading("Conflicts!");
@@ -314,16 +908,16 @@ impl App {
                 ui.separator();
                 ui.label(REPO_CONFLICT_EXPLENATION.replace(" ", "  "));
 
-                // let mut button = egui::Button::new("Delete all local progress");
-                // if ui.style().visuals.dark_mode {
-                //     button = button.fill(Color32::from_rgb(130, 10, 10));
-                // } else {
-                //     button = button.fill(Color32::from_rgb(255, 150, 150));
-                // }
-                
-                // if ui.add(button).clicked() {
-                //     self.backend.
-                // }
+                let mut button = egui::Button::new("Delete all local progress");
+                if ui.style().visuals.dark_mode {
+                    button = button.fill(Color32::from_rgb(130, 10, 10));
+                } else {
+                    button = button.fill(Color32::from_rgb(255, 150, 150));
+                }
+
+                if ui.add(button).clicked() {
+                    self.backend.discard_local_changes();
+                }
             }
             Scene::Error {
                 title,
@@ -342,6 +936,35 @@ impl App {
     }
 }
 
+/// Bytes in MB, or KB if it's small
+pub fn format_size(bytes: u64) -> String {
+    if bytes < 1024 * 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.)
+    } else {
+        format!("{:.1} MB", bytes as f64 / (1024. * 1024.))
+    }
+}
+
+/// Local date and time, like `2022-10-18 21:05`.
+pub fn format_date(time: std::time::SystemTime) -> String {
+    chrono::DateTime::<chrono::Local>::from(time)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+pub fn format_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 60 * 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else if secs < 24 * 60 * 60 {
+        format!("{}h {}m", secs / (60 * 60), secs / 60 % 60)
+    } else {
+        format!("{}d {}h", secs / (24 * 60 * 60), secs / (60 * 60) % 24)
+    }
+}
+
 const REPO_CONFLICT_EXPLENATION: &str = "
 Conflicts found
 Contact with a Moderator
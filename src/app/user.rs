@@ -1,4 +1,4 @@
-use crate::public_ip;
+use crate::{git, public_ip};
 
 pub fn id<S: AsRef<str>>(username: Option<S>) -> String {
     let ip = public_ip::get().expect("Could not get public ip address");
@@ -29,3 +29,9 @@ pub fn display<S: AsRef<str>>(user_id: S) -> String {
         },
     }
 }
+
+/// What `display` shows of the `id` of this user.
+/// `None` if they don't have a username and the public ip can't be read.
+pub fn display_name() -> Option<String> {
+    git::get_username().ok().or_else(public_ip::get)
+}
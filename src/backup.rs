@@ -0,0 +1,365 @@
+use crate::error::Error;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Backups kept of each reason, the oldest ones are deleted.
+/// Counted apart so the frequent ones, like `reset`, don't delete the rare ones.
+const KEPT_BACKUPS: usize = 10;
+/// History backups kept, they are a copy of the whole `.git` folder.
+const KEPT_HISTORY_BACKUPS: usize = 2;
+/// Backups older than this are deleted, except the newest one of each reason.
+const MAX_BACKUP_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const BACKUP_EXTENSION: &str = ".tar.gz";
+/// Folders of the work dir that are not part of the world.
+const STATE_DIRS: [&str; 2] = [".git", ".world_store"];
+
+/// Reason of the backups made before pruning the history.
+/// They have the `.git` folder instead of the world, so they can't be restored from the app.
+pub const HISTORY_REASON: &str = "prune";
+
+/// Compressed copy of some files of the world, made before an operation that could lose them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub created: SystemTime,
+    /// Operation that made the backup, like `reset` or `reclone`.
+    pub reason: String,
+    /// Bytes of the compressed file.
+    pub size: u64,
+}
+
+impl Backup {
+    pub fn is_history(&self) -> bool {
+        self.reason == HISTORY_REASON
+    }
+}
+
+/// Folder with the backups of the world on `work_dir`, next to it.
+pub fn backups_dir(work_dir: &Path) -> PathBuf {
+    let name = work_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    work_dir.with_file_name(format!("{} backups", name))
+}
+
+/// Every file of the world, without the repository state.
+pub fn world_files(work_dir: &Path) -> Result<Vec<String>, Error> {
+    let mut files = vec![];
+    if work_dir.exists() {
+        list_files(work_dir, work_dir, &STATE_DIRS, &mut files)?;
+    }
+    Ok(files)
+}
+
+/// Every file of the `.git` folder, with the whole history.
+pub fn history_files(work_dir: &Path) -> Result<Vec<String>, Error> {
+    let mut files = vec![];
+    list_files(work_dir, &work_dir.join(".git"), &[], &mut files)?;
+    Ok(files)
+}
+
+/// Compresses the `files` of `work_dir` into a new backup, then deletes the old backups.
+/// Files that don't exist are skipped, and if none exists no backup is made.
+/// `progress` is called with the files done and the total.
+pub fn create<F>(
+    work_dir: &Path,
+    reason: &str,
+    files: &[String],
+    progress: F,
+) -> Result<Option<PathBuf>, Error>
+where
+    F: FnMut(usize, usize),
+{
+    let path = write_backup(work_dir, reason, files, progress)?;
+    remove_old_backups(work_dir)?;
+    Ok(path)
+}
+
+fn write_backup<F>(
+    work_dir: &Path,
+    reason: &str,
+    files: &[String],
+    mut progress: F,
+) -> Result<Option<PathBuf>, Error>
+where
+    F: FnMut(usize, usize),
+{
+    let files: Vec<&String> = files
+        .iter()
+        .filter(|file| work_dir.join(file).is_file())
+        .collect();
+    if files.is_empty() {
+        return Ok(None);
+    }
+
+    let dir = backups_dir(work_dir);
+    fs::create_dir_all(&dir)?;
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or_default();
+    let mut path = dir.join(format!("{} {}{}", millis, reason, BACKUP_EXTENSION));
+    let mut copy = 1;
+    while path.exists() {
+        copy += 1;
+        path = dir.join(format!(
+            "{} {} {}{}",
+            millis, reason, copy, BACKUP_EXTENSION
+        ));
+    }
+
+    // Written apart so a half written backup is never listed
+    let partial_path = dir.join("partial");
+    let encoder = GzEncoder::new(File::create(&partial_path)?, Compression::default());
+    let mut archive = tar::Builder::new(encoder);
+    for (done, file) in files.iter().enumerate() {
+        progress(done, files.len());
+        archive.append_path_with_name(work_dir.join(file), file)?;
+    }
+    archive.into_inner()?.finish()?;
+    fs::rename(&partial_path, &path)?;
+    Ok(Some(path))
+}
+
+/// Backups of the world on `work_dir`, the newest first.
+pub fn list(work_dir: &Path) -> Result<Vec<Backup>, Error> {
+    let dir = backups_dir(work_dir);
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut backups = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some((millis, reason)) = name
+            .strip_suffix(BACKUP_EXTENSION)
+            .and_then(|name| name.split_once(' '))
+        else {
+            continue;
+        };
+        let Ok(millis) = millis.parse() else {
+            continue;
+        };
+        let reason = match reason.rsplit_once(' ') {
+            // The number added to not replace another backup of the same millisecond
+            Some((reason, copy)) if copy.parse::<u32>().is_ok() => reason,
+            _ => reason,
+        };
+
+        backups.push(Backup {
+            path: entry.path(),
+            created: UNIX_EPOCH + Duration::from_millis(millis),
+            reason: reason.into(),
+            size: entry.metadata()?.len(),
+        });
+    }
+    backups.sort_by_key(|backup| Reverse(backup.created));
+    Ok(backups)
+}
+
+/// Writes the files of the backup over the world.
+/// The files that it replaces are backed up first, so the restore can be undone.
+pub fn restore<F>(work_dir: &Path, backup: &Path, mut progress: F) -> Result<(), Error>
+where
+    F: FnMut(usize, usize),
+{
+    let mut files = vec![];
+    for entry in open_archive(backup)?.entries()? {
+        let path = entry?.path()?.to_string_lossy().replace('\\', "/");
+        if STATE_DIRS.iter().any(|dir| is_inside(&path, dir)) {
+            return Err(Error::from_str(
+                "The backup has the history, it can't be restored over the world",
+            ));
+        }
+        files.push(path);
+    }
+
+    // The old backups are deleted after, the restored one could be one of them
+    write_backup(work_dir, "restore", &files, &mut progress)?;
+
+    for (done, entry) in open_archive(backup)?.entries()?.enumerate() {
+        progress(done, files.len());
+        entry?.unpack_in(work_dir)?;
+    }
+    remove_old_backups(work_dir)
+}
+
+/// `path` followed by the current time, and a number if it's already taken.
+pub fn free_path(path: PathBuf) -> PathBuf {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default();
+    let name = path.into_os_string().to_string_lossy().to_string();
+
+    let mut path = PathBuf::from(format!("{} {}", name, seconds));
+    let mut copy = 1;
+    while path.exists() {
+        copy += 1;
+        path = PathBuf::from(format!("{} {} ({})", name, seconds, copy));
+    }
+    path
+}
+
+fn open_archive(path: &Path) -> io::Result<tar::Archive<GzDecoder<File>>> {
+    Ok(tar::Archive::new(GzDecoder::new(File::open(path)?)))
+}
+
+/// Keeps the newest `KEPT_BACKUPS` of each reason that are younger than `MAX_BACKUP_AGE`,
+/// and always the newest one of each reason.
+fn remove_old_backups(work_dir: &Path) -> Result<(), Error> {
+    let mut newer_backups = HashMap::new();
+    for backup in list(work_dir)? {
+        let newer = newer_backups.entry(backup.reason.clone()).or_insert(0);
+        let kept = if backup.is_history() {
+            KEPT_HISTORY_BACKUPS
+        } else {
+            KEPT_BACKUPS
+        };
+        let too_old = backup
+            .created
+            .elapsed()
+            .map(|age| age > MAX_BACKUP_AGE)
+            .unwrap_or(false);
+        let remove = *newer >= kept || (*newer > 0 && too_old);
+        *newer += 1;
+        if remove {
+            fs::remove_file(backup.path)?;
+        }
+    }
+    Ok(())
+}
+
+/// `path` is `dir` or is inside of it.
+fn is_inside(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .map(|rest| rest.is_empty() || rest.starts_with('/'))
+        .unwrap_or(false)
+}
+
+/// Paths of the files inside `dir`, relative to `root` and with `/`.
+/// The `skipped` folders of `root` are not listed.
+fn list_files(
+    root: &Path,
+    dir: &Path,
+    skipped: &[&str],
+    files: &mut Vec<String>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if dir == root && skipped.iter().any(|skipped| entry.file_name() == *skipped) {
+                continue;
+            }
+            list_files(root, &path, skipped, files)?;
+        } else if let Ok(local_path) = path.strip_prefix(root) {
+            files.push(local_path.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_work_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let work_dir = dir.join("world");
+        fs::create_dir_all(backups_dir(&work_dir)).unwrap();
+        work_dir
+    }
+
+    /// An empty backup file, only its name is read when listing.
+    fn add_backup(work_dir: &Path, age: Duration, name: &str) {
+        let millis = (SystemTime::now() - age)
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let path = backups_dir(work_dir).join(format!("{} {}{}", millis, name, BACKUP_EXTENSION));
+        File::create(path).unwrap();
+    }
+
+    fn reasons(work_dir: &Path) -> Vec<String> {
+        list(work_dir)
+            .unwrap()
+            .into_iter()
+            .map(|backup| backup.reason)
+            .collect()
+    }
+
+    #[test]
+    fn copy_number_is_not_part_of_the_reason() {
+        let work_dir = temp_work_dir("backup-names");
+        let dir = backups_dir(&work_dir);
+        File::create(dir.join("1000 reset.tar.gz")).unwrap();
+        File::create(dir.join("2000 reset 2.tar.gz")).unwrap();
+        File::create(dir.join("3000 set aside.tar.gz")).unwrap();
+        // Not backups
+        File::create(dir.join("partial")).unwrap();
+        File::create(dir.join("notes reset.tar.gz")).unwrap();
+
+        let backups = list(&work_dir).unwrap();
+        assert_eq!(reasons(&work_dir), ["set aside", "reset", "reset"]);
+        assert_eq!(backups[1].created, UNIX_EPOCH + Duration::from_millis(2000));
+
+        let _ = fs::remove_dir_all(work_dir.parent().unwrap());
+    }
+
+    #[test]
+    fn backups_are_kept_by_reason() {
+        let work_dir = temp_work_dir("backup-retention");
+        for minutes in 0..KEPT_BACKUPS as u64 + 5 {
+            add_backup(&work_dir, Duration::from_secs(60 * (minutes + 1)), "reset");
+        }
+        for minutes in 0..KEPT_HISTORY_BACKUPS as u64 + 1 {
+            add_backup(&work_dir, Duration::from_secs(60 * minutes), HISTORY_REASON);
+        }
+        // Older than all the resets, they must not remove it
+        add_backup(&work_dir, Duration::from_secs(60 * 60), "reclone");
+
+        remove_old_backups(&work_dir).unwrap();
+
+        let backups = list(&work_dir).unwrap();
+        let count = |reason: &str| backups.iter().filter(|b| b.reason == reason).count();
+        assert_eq!(count("reset"), KEPT_BACKUPS);
+        assert_eq!(count(HISTORY_REASON), KEPT_HISTORY_BACKUPS);
+        assert_eq!(count("reclone"), 1);
+
+        // The newest ones are kept
+        let oldest_reset = backups.iter().rfind(|b| b.reason == "reset");
+        let age = oldest_reset.unwrap().created.elapsed().unwrap();
+        assert!(age < Duration::from_secs(60 * (KEPT_BACKUPS as u64 + 1)));
+
+        let _ = fs::remove_dir_all(work_dir.parent().unwrap());
+    }
+
+    #[test]
+    fn newest_backup_of_each_reason_is_kept_however_old() {
+        let work_dir = temp_work_dir("backup-age");
+        let old = MAX_BACKUP_AGE + Duration::from_secs(60 * 60);
+        add_backup(&work_dir, old, "reclone");
+        add_backup(&work_dir, old * 2, "reclone");
+        add_backup(&work_dir, Duration::ZERO, "reset");
+        add_backup(&work_dir, old, "reset");
+
+        remove_old_backups(&work_dir).unwrap();
+
+        let backups = list(&work_dir).unwrap();
+        assert_eq!(reasons(&work_dir), ["reset", "reclone"]);
+        assert!(backups[1].created.elapsed().unwrap() < old * 2);
+
+        let _ = fs::remove_dir_all(work_dir.parent().unwrap());
+    }
+}
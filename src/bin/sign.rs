@@ -0,0 +1,180 @@
+//! Tool for moderators to sign the server files.
+//!
+//! ```text
+//! sign keygen <private key file>
+//! sign pubkey <private key file>
+//! sign file <private key file> <file>...
+//! sign folder <private key file> <server folder>
+//! sign verify <server folder> [file]
+//! ```
+
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use shared_minecraft_server::{
+    manifest::{self, Manifest, MANIFEST_LOCAL_PATH},
+    verify_signature::{
+        self, key_id, signature_path, to_hex, update_trusted_keys, verify_signature,
+    },
+};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+const USAGE: &str = "Usage:
+    sign keygen <private key file>              Generates a new ed25519 key pair
+    sign pubkey <private key file>              Prints the public key and its id
+    sign file <private key file> <file>...      Writes <file>.ed25519 for every file
+    sign folder <private key file> <folder>     Writes and signs the manifest of a server folder
+    sign verify <folder> [file]                 Checks the signatures like the app does";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args.as_slice() {
+        ["keygen", key_file] => keygen(Path::new(key_file)),
+        ["pubkey", key_file] => load_key(Path::new(key_file)).map(|key| print_public_key(&key)),
+        ["file", key_file, files @ ..] if !files.is_empty() => {
+            sign_files(Path::new(key_file), files)
+        }
+        ["folder", key_file, folder] => sign_folder(Path::new(key_file), Path::new(folder)),
+        ["verify", folder] => verify_folder(Path::new(folder)),
+        ["verify", folder, file] => verify_file(Path::new(folder), Path::new(file)),
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+        exit(1);
+    }
+}
+
+fn keygen(key_file: &Path) -> io::Result<()> {
+    if key_file.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", key_file.display()),
+        ));
+    }
+
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| io::Error::other("Could not generate key pair"))?;
+    std::fs::write(key_file, pkcs8.as_ref())?;
+
+    let key = load_key(key_file)?;
+    println!(
+        "Private key saved on {}. Keep it secret!",
+        key_file.display()
+    );
+    print_public_key(&key);
+    println!(
+        "Add it to {} as: key={} <your name>",
+        verify_signature::TRUSTED_KEYS_LOCAL_PATH,
+        to_hex(key.public_key().as_ref())
+    );
+    Ok(())
+}
+
+fn load_key(key_file: &Path) -> io::Result<Ed25519KeyPair> {
+    let pkcs8 = std::fs::read(key_file)?;
+    Ed25519KeyPair::from_pkcs8(&pkcs8).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not an ed25519 private key", key_file.display()),
+        )
+    })
+}
+
+fn public_key(key: &Ed25519KeyPair) -> [u8; 32] {
+    let mut public_key = [0; 32];
+    public_key.copy_from_slice(key.public_key().as_ref());
+    public_key
+}
+
+fn print_public_key(key: &Ed25519KeyPair) {
+    let public_key = public_key(key);
+    println!("Public key: {}", to_hex(&public_key));
+    println!("Key id:     {}", to_hex(&key_id(&public_key)));
+}
+
+/// Writes `<key id><signature>`, the format that `verify_signature` expects.
+fn sign_file(key: &Ed25519KeyPair, file: &Path) -> io::Result<PathBuf> {
+    let data = std::fs::read(file)?;
+    let mut signature = key_id(&public_key(key)).to_vec();
+    signature.extend_from_slice(key.sign(&data).as_ref());
+
+    let path = signature_path(file);
+    std::fs::write(&path, signature)?;
+    Ok(path)
+}
+
+fn sign_files(key_file: &Path, files: &[&str]) -> io::Result<()> {
+    let key = load_key(key_file)?;
+    for file in files {
+        let path = sign_file(&key, Path::new(file))?;
+        println!("Signed {}", path.display());
+    }
+    Ok(())
+}
+
+fn sign_folder(key_file: &Path, folder: &Path) -> io::Result<()> {
+    let key = load_key(key_file)?;
+    let manifest = Manifest::from_dir(folder)?;
+    let manifest_path = folder.join(MANIFEST_LOCAL_PATH);
+
+    std::fs::write(&manifest_path, manifest.to_string())?;
+    sign_file(&key, &manifest_path)?;
+
+    print!("{}", manifest);
+    println!("Signed {}", manifest_path.display());
+    Ok(())
+}
+
+/// Loads the trusted keys of the folder without touching the cache of the app.
+fn load_trusted_keys(folder: &Path) -> io::Result<()> {
+    let cache_file =
+        std::env::temp_dir().join(format!("sign-trusted-keys-{}.txt", std::process::id()));
+    let result = update_trusted_keys(folder, &cache_file);
+    let _ = std::fs::remove_file(cache_file);
+    result
+}
+
+fn verify_folder(folder: &Path) -> io::Result<()> {
+    load_trusted_keys(folder)?;
+
+    let untrusted = manifest::check(folder)?;
+    for file in &untrusted {
+        println!("{}", file);
+    }
+
+    if untrusted.is_empty() {
+        println!("All the executable files are trusted.");
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} files are not trusted", untrusted.len()),
+        ))
+    }
+}
+
+fn verify_file(folder: &Path, file: &Path) -> io::Result<()> {
+    load_trusted_keys(folder)?;
+
+    if verify_signature(file) {
+        println!("{} has a valid signature.", file.display());
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} doesn't have a valid signature", file.display()),
+        ))
+    }
+}
@@ -3,6 +3,10 @@ use std::panic::Location;
 #[derive(Debug)]
 enum InnerError {
     Msg(String),
+    /// The origin moved while uploading.
+    Rejected(String),
+    /// The user cancelled the operation.
+    Cancelled,
     Io(std::io::Error),
     Git(git2::Error),
     Http(http_req::error::Error),
@@ -22,12 +26,95 @@ impl Error {
             location: std::panic::Location::caller(),
         }
     }
+
+    #[track_caller]
+    pub fn cancelled() -> Self {
+        Self {
+            inner: InnerError::Cancelled,
+            location: std::panic::Location::caller(),
+        }
+    }
+
+    #[track_caller]
+    pub fn rejected<S: Into<String>>(msg: S) -> Self {
+        Self {
+            inner: InnerError::Rejected(msg.into()),
+            location: std::panic::Location::caller(),
+        }
+    }
+
+    /// The remote rejected the credentials, or there weren't any.
+    pub fn is_auth(&self) -> bool {
+        match &self.inner {
+            InnerError::Git(err) => {
+                err.code() == git2::ErrorCode::Auth
+                    || (err.class() == git2::ErrorClass::Http
+                        && (err.message().contains("401") || err.message().contains("403")))
+            }
+            _ => false,
+        }
+    }
+
+    /// The origin could not be reached, the local copy is still usable.
+    pub fn is_network(&self) -> bool {
+        use std::io::ErrorKind;
+        match &self.inner {
+            InnerError::Git(err) => {
+                !self.is_auth()
+                    && matches!(
+                        err.class(),
+                        git2::ErrorClass::Net | git2::ErrorClass::Http | git2::ErrorClass::Ssl
+                    )
+            }
+            InnerError::Io(err) => matches!(
+                err.kind(),
+                ErrorKind::NotConnected
+                    | ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::TimedOut
+            ),
+            InnerError::Http(_) => true,
+            InnerError::Msg(_) | InnerError::Rejected(_) | InnerError::Cancelled => false,
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.inner, InnerError::Cancelled)
+    }
+
+    /// The push was rejected because the remote has commits that are not merged,
+    /// someone else pushed first.
+    pub fn is_push_rejected(&self) -> bool {
+        match &self.inner {
+            InnerError::Git(err) => err.code() == git2::ErrorCode::NotFastForward,
+            InnerError::Rejected(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Failures that may go away by trying again, like a dropped connection
+    /// or a repo locked by another process.
+    pub fn is_transient(&self) -> bool {
+        match &self.inner {
+            InnerError::Git(err) if err.code() == git2::ErrorCode::Locked => true,
+            InnerError::Io(err)
+                if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::Interrupted | std::io::ErrorKind::WouldBlock
+                ) =>
+            {
+                true
+            }
+            _ => self.is_network(),
+        }
+    }
 }
 
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match &self.inner {
-            InnerError::Msg(_) => None,
+            InnerError::Msg(_) | InnerError::Rejected(_) | InnerError::Cancelled => None,
             InnerError::Io(err) => Some(err),
             InnerError::Git(err) => Some(err),
             InnerError::Http(err) => Some(err),
@@ -38,7 +125,10 @@ impl std::error::Error for Error {
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self.inner {
-            InnerError::Msg(msg) => write!(f, "{}, {}", msg, self.location),
+            InnerError::Msg(msg) | InnerError::Rejected(msg) => {
+                write!(f, "{}, {}", msg, self.location)
+            }
+            InnerError::Cancelled => write!(f, "Cancelled, {}", self.location),
             _ => write!(f, "{:?}, {}", self.inner, self.location),
         }
     }
@@ -1,9 +1,127 @@
-use git2::{Cred, Error};
-
-pub fn create_credentials() -> Result<Cred, Error> {
-    let header = String::from("ghp");
-    Cred::userpass_plaintext(
-        "Octova-Handle",
-        &(header + &env!("PUBLIC_GITHUB_TOKEN_BODY")),
-    )
+use git2::{Config, Cred, CredentialType, Error, ErrorClass, ErrorCode};
+use std::path::PathBuf;
+
+const KEYRING_SERVICE: &str = "shared-minecraft-server";
+const KEYRING_LOGIN_ENTRY: &str = "login";
+const SSH_KEY_NAMES: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// Account and access token saved by the login scene.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Login {
+    pub username: String,
+    pub token: String,
+}
+
+/// Reads the login from the OS keychain.
+pub fn saved_login() -> Option<Login> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, KEYRING_LOGIN_ENTRY).ok()?;
+    let secret = entry.get_password().ok()?;
+    let (username, token) = secret.split_once(':')?;
+    Some(Login {
+        username: username.into(),
+        token: token.into(),
+    })
+}
+
+/// Saves the login on the OS keychain.
+pub fn save_login(login: &Login) -> Result<(), crate::error::Error> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_LOGIN_ENTRY)
+        .and_then(|entry| entry.set_password(&format!("{}:{}", login.username, login.token)))
+        .map_err(|err| crate::error::Error::from_str(format!("Could not save login: {}", err)))
+}
+
+/// Checks if there is any credential that could be used to push to `url`.
+/// It doesn't check if the credential is accepted.
+pub fn has_credentials(url: &str) -> bool {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        saved_login().is_some()
+            || Config::open_default()
+                .and_then(|config| config.get_string("credential.helper"))
+                .map(|helper| !helper.is_empty())
+                .unwrap_or(false)
+    } else {
+        std::env::var_os("SSH_AUTH_SOCK").is_some() || !ssh_keys().is_empty()
+    }
+}
+
+/// Private keys found on `~/.ssh`
+fn ssh_keys() -> Vec<PathBuf> {
+    let Some(dirs) = directories::UserDirs::new() else {
+        return vec![];
+    };
+    let ssh_dir = dirs.home_dir().join(".ssh");
+    SSH_KEY_NAMES
+        .iter()
+        .map(|name| ssh_dir.join(name))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Gives the credentials of the user, in order:
+/// the login saved on the OS keychain, the git credential helper, the SSH agent and the SSH keys.
+///
+/// libgit2 asks again when the server rejects a credential,
+/// so every source is only tried once to not loop forever.
+#[derive(Default)]
+pub struct CredentialResolver {
+    tried_login: bool,
+    tried_helper: bool,
+    tried_agent: bool,
+    tried_ssh_keys: usize,
+}
+
+impl CredentialResolver {
+    pub fn resolve(
+        &mut self,
+        url: &str,
+        username_from_url: Option<&str>,
+        allowed: CredentialType,
+    ) -> Result<Cred, Error> {
+        let ssh_username = username_from_url.unwrap_or("git");
+
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(ssh_username);
+        }
+
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            if !self.tried_login {
+                self.tried_login = true;
+                if let Some(login) = saved_login() {
+                    return Cred::userpass_plaintext(&login.username, &login.token);
+                }
+            }
+
+            if !self.tried_helper {
+                self.tried_helper = true;
+                if let Ok(cred) = Config::open_default()
+                    .and_then(|config| Cred::credential_helper(&config, url, username_from_url))
+                {
+                    return Ok(cred);
+                }
+            }
+        }
+
+        if allowed.contains(CredentialType::SSH_KEY) {
+            if !self.tried_agent {
+                self.tried_agent = true;
+                if let Ok(cred) = Cred::ssh_key_from_agent(ssh_username) {
+                    return Ok(cred);
+                }
+            }
+
+            let keys = ssh_keys();
+            while let Some(key) = keys.get(self.tried_ssh_keys) {
+                self.tried_ssh_keys += 1;
+                if let Ok(cred) = Cred::ssh_key(ssh_username, None, key, None) {
+                    return Ok(cred);
+                }
+            }
+        }
+
+        Err(Error::new(
+            ErrorCode::Auth,
+            ErrorClass::Net,
+            "There are no valid credentials to push to the server",
+        ))
+    }
 }
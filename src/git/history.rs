@@ -0,0 +1,93 @@
+use super::{Git, StatusReporter, LOCK_BRANCH, LOCK_FILE};
+use crate::error::Error;
+use git2::{Commit, Sort};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A commit that changed the host of the lock.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LockChange {
+    pub time: SystemTime,
+    /// Id of the new host, `None` if it was unlocked.
+    pub host: Option<String>,
+}
+
+/// A commit of main.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub time: SystemTime,
+    pub author: String,
+    pub message: String,
+}
+
+impl<R: StatusReporter> Git<R> {
+    /// Every change of the lock, the oldest first.
+    /// The lock branch is read, and main for the snapshots from when the lock was a file of the world.
+    pub fn lock_history(&self) -> Result<Vec<LockChange>, Error> {
+        self.deepen()?;
+        let lock_ref = format!("refs/remotes/origin/{}", LOCK_BRANCH);
+        let mut changes = vec![];
+        for reference in [lock_ref.as_str(), "refs/heads/main"] {
+            if self.repo.find_reference(reference).is_err() {
+                continue;
+            }
+
+            let mut host = None;
+            for commit in self.walk(reference, Sort::TIME | Sort::REVERSE)? {
+                let commit = commit?;
+                let commit_host = self.lock_host(&commit)?;
+                if commit_host != host {
+                    host = commit_host;
+                    changes.push(LockChange {
+                        time: commit_time(&commit),
+                        host: host.clone(),
+                    });
+                }
+            }
+        }
+        changes.sort_by_key(|change| change.time);
+        Ok(changes)
+    }
+
+    /// Snapshots of main, the newest first.
+    pub fn snapshots(&self) -> Result<Vec<Snapshot>, Error> {
+        self.deepen()?;
+        let mut snapshots = vec![];
+        for commit in self.walk("refs/heads/main", Sort::TIME)? {
+            let commit = commit?;
+            snapshots.push(Snapshot {
+                time: commit_time(&commit),
+                author: String::from_utf8_lossy(commit.author().name_bytes()).to_string(),
+                message: String::from_utf8_lossy(commit.message_bytes()).to_string(),
+            });
+        }
+        Ok(snapshots)
+    }
+
+    /// Host written on the lock file of the commit, `None` if it's unlocked.
+    fn lock_host(&self, commit: &Commit) -> Result<Option<String>, Error> {
+        let tree = commit.tree()?;
+        let Some(entry) = tree.get_name(LOCK_FILE) else {
+            return Ok(None);
+        };
+        let blob = entry.to_object(&self.repo)?.peel_to_blob()?;
+        let host = String::from_utf8_lossy(blob.content()).trim().to_string();
+        Ok(if host.is_empty() { None } else { Some(host) })
+    }
+
+    /// First parent history of `reference`.
+    fn walk(
+        &self,
+        reference: &str,
+        sort: Sort,
+    ) -> Result<impl Iterator<Item = Result<Commit<'_>, Error>>, Error> {
+        let mut walk = self.repo.revwalk()?;
+        walk.push_ref(reference)?;
+        walk.simplify_first_parent()?;
+        walk.set_sorting(sort)?;
+        Ok(walk.map(|oid| Ok(self.repo.find_commit(oid?)?)))
+    }
+}
+
+fn commit_time(commit: &Commit) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(commit.time().seconds().max(0) as u64)
+}
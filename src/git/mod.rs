@@ -1,11 +1,29 @@
 mod credentials;
+mod history;
+mod repair;
 mod status_reporter;
 
-use crate::error::Error;
+use crate::{backup, error::Error, world_store::MergeStatus};
+pub use credentials::{has_credentials, save_login, saved_login, Login};
 use git2::{build::RepoBuilder, *};
-pub use status_reporter::StatusReporter;
+pub use history::{LockChange, Snapshot};
 use status_reporter::*;
-use std::path::{Path, PathBuf};
+pub use status_reporter::{StatusReporter, TransferProgress};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Branch with only the lock, so checking it doesn't download the world.
+const LOCK_BRANCH: &str = "lock";
+/// File of the lock branch with the id of the host.
+/// Older versions keep it on main, next to the world.
+const LOCK_FILE: &str = "hoster.txt";
+/// Depth that fetches all the history of a shallow clone, `GIT_FETCH_DEPTH_UNSHALLOW` of libgit2.
+const UNSHALLOW_DEPTH: i32 = i32::MAX;
+/// Author of the snapshots of users without a git identity nor a login.
+const DEFAULT_AUTHOR_NAME: &str = "Octova-Handle";
+const DEFAULT_AUTHOR_EMAIL: &str = "octova.handle@gmail.com";
 
 pub struct Git<R: StatusReporter> {
     path: PathBuf,
@@ -13,24 +31,27 @@ pub struct Git<R: StatusReporter> {
     reporter: GitStatusReporter<R>,
 }
 
-pub enum MergeStatus {
-    Ok,
-    // Merge has been aborted because it could not fast forward.
-    Conflicts(Vec<String>),
-}
-
 impl<R: StatusReporter> Git<R> {
     /// It will open a repo if exists and clone from url if not.
     /// If needed it will create the path.
+    /// A repo that can't be opened is cloned again, other damages are fixed by `repair`.
     pub fn new<P: AsRef<Path>>(reporter: R, path: P, origin_url: &str) -> Result<Self, Error> {
-        if let Some(repo) = Self::open(path.as_ref())? {
-            Ok(Self {
+        match Self::open(path.as_ref()) {
+            Ok(Some(repo)) => Ok(Self {
                 path: path.as_ref().into(),
                 repo,
                 reporter: reporter.into(),
-            })
-        } else {
-            Self::clone(reporter, path, origin_url)
+            }),
+            Ok(None) => {
+                // A repo without branches has no snapshots, like the clone of an empty origin
+                let git_dir = path.as_ref().join(".git");
+                if git_dir.exists() {
+                    std::fs::remove_dir_all(git_dir)?;
+                }
+                Self::clone(reporter, path, origin_url)
+            }
+            // The repo is too damaged to be opened
+            Err(_) => Self::reclone_aside(reporter.into(), path.as_ref().into(), origin_url),
         }
     }
 
@@ -45,25 +66,36 @@ impl<R: StatusReporter> Git<R> {
             }
             Err(e) => match e.code() {
                 ErrorCode::NotFound => Ok(None),
-                _ => {
-                    panic!("Error reading server app folder. {:?}", e);
-                }
+                _ => Err(e.into()),
             },
         }
     }
 
-    fn clone<P: AsRef<Path>>(reporter: R, path: P, origin_url: &str) -> Result<Self, Error> {
+    fn clone<P: AsRef<Path>>(
+        reporter: impl Into<GitStatusReporter<R>>,
+        path: P,
+        origin_url: &str,
+    ) -> Result<Self, Error> {
         let reporter: GitStatusReporter<R> = reporter.into();
 
-        std::fs::create_dir_all(path.as_ref())?;
-
-        // Clean folder content for fresh install
-        std::fs::remove_dir_all(path.as_ref())?;
-
+        // The folder must be empty, a clone never replaces files
+        // Only main is cloned, the lock branch is fetched apart and other branches are never used.
+        // Only the last snapshot is downloaded, the history is fetched when it's needed (see `deepen`).
+        // libgit2 removes what it has cloned if it fails, so a half clone is never opened
+        let mut fetch_options = reporter.new_fetch_options();
+        // libgit2 can't make a shallow clone of a local repo, and it's fast to copy anyway
+        let local = origin_url.starts_with("file://") || Path::new(origin_url).exists();
+        if !local {
+            fetch_options.depth(1);
+        }
         let repo = RepoBuilder::new()
-            .fetch_options(reporter.new_fetch_options())
+            .remote_create(|repo, name, url| {
+                repo.remote_with_fetch(name, url, "+refs/heads/main:refs/remotes/origin/main")
+            })
+            .fetch_options(fetch_options)
             .with_checkout(reporter.new_checkout())
-            .clone(origin_url, path.as_ref())?;
+            .clone(origin_url, path.as_ref())
+            .map_err(|err| reporter.error(err))?;
 
         Ok(Self {
             path: path.as_ref().into(),
@@ -73,41 +105,106 @@ impl<R: StatusReporter> Git<R> {
     }
 
     /// If there aren't changes, it will not commit.
+    /// Only the files that the status reports are hashed,
+    /// the status skips unchanged files by their size and mtime.
     pub fn commit_all(&self, message: &str) -> Result<(), Error> {
+        self.commit_changes(message, false)
+    }
+
+    /// Like `commit_all`, but it commits even if there aren't changes.
+    pub fn commit_all_forced(&self, message: &str) -> Result<(), Error> {
+        self.commit_changes(message, true)
+    }
+
+    fn commit_changes(&self, message: &str, allow_empty: bool) -> Result<(), Error> {
+        self.reporter.status_change("Listing changes", None);
+
+        // Ignored files are included, like a forced `add --all`
+        let mut status_options = StatusOptions::new();
+        status_options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .include_ignored(true)
+            .recurse_ignored_dirs(true);
+        let statuses = self.repo.statuses(Some(&mut status_options))?;
+
+        let worktree_changes = Status::WT_NEW
+            | Status::WT_MODIFIED
+            | Status::WT_DELETED
+            | Status::WT_TYPECHANGE
+            | Status::WT_RENAMED
+            | Status::IGNORED;
+
+        let changes: Vec<(String, Status)> = statuses
+            .iter()
+            .filter(|entry| entry.status().intersects(worktree_changes))
+            .filter_map(|entry| Some((entry.path()?.to_string(), entry.status())))
+            .collect();
+        let staged = statuses.iter().any(|entry| {
+            entry.status().intersects(
+                Status::INDEX_NEW
+                    | Status::INDEX_MODIFIED
+                    | Status::INDEX_DELETED
+                    | Status::INDEX_RENAMED
+                    | Status::INDEX_TYPECHANGE,
+            )
+        });
+        drop(statuses);
+
         let mut index = self.repo.index()?;
-        let mut found_changes = false;
-
-        index.add_all(
-            ["*"].iter(),
-            IndexAddOption::FORCE,
-            Some(&mut |_path: &Path, _matched_spec: &[u8]| -> i32 {
-                found_changes = true;
-                self.reporter.status_change("Listing changes", Some(0.));
-                0
-            }),
-        )?;
+        for (done, (path, status)) in changes.iter().enumerate() {
+            self.reporter
+                .status_count("Staging changes", done, changes.len());
 
-        if found_changes {
+            if status.contains(Status::WT_DELETED) {
+                index.remove_path(Path::new(path))?;
+            } else {
+                index.add_path(Path::new(path))?;
+            }
+        }
+
+        if allow_empty || staged || !changes.is_empty() {
             index.write()?;
             let tree = self.repo.find_tree(index.write_tree_to(&self.repo)?)?;
-            self.commit(message, &tree, &[&self.repo.head()?.peel_to_commit()?])?;
+            match self.repo.head() {
+                Ok(head) => self.commit(message, &tree, &[&head.peel_to_commit()?])?,
+                // The first snapshot of an empty origin
+                Err(err) if err.code() == ErrorCode::UnbornBranch => {
+                    self.repo.set_head("refs/heads/main")?;
+                    self.commit(message, &tree, &[])?;
+                }
+                Err(err) => return Err(err.into()),
+            }
         }
         Ok(())
     }
 
     fn commit(&self, msg: &str, tree: &Tree, parents: &[&Commit]) -> Result<(), Error> {
-        let s = Signature::now("Octova-Handle", "octova.handle@gmail.com")?;
+        let s = self.signature()?;
         self.reporter.status_change("Packing changes", Some(0.));
         self.repo.commit(Some("HEAD"), &s, &s, msg, tree, parents)?;
         Ok(())
     }
 
+    /// The user is the author of their snapshots and lock changes, so the history tells who hosted.
+    /// It's their git identity, or the username of the login if they don't have one.
+    fn signature(&self) -> Result<Signature<'static>, Error> {
+        if let Ok(signature) = self.repo.signature() {
+            return Ok(signature);
+        }
+        match saved_login() {
+            Some(login) => Ok(Signature::now(&login.username, "")?),
+            None => Ok(Signature::now(DEFAULT_AUTHOR_NAME, DEFAULT_AUTHOR_EMAIL)?),
+        }
+    }
+
     // squashes changes into the last commit
     pub fn commit_all_squashed(&self, message: &str) -> Result<(), Error> {
         let mut commit = self.repo.head()?.peel_to_commit()?;
         if commit.parent_count() > 0 {
             commit = commit.parent(0)?;
         }
+        self.backup_changes_from(&commit.tree()?, "squash")?;
 
         let mut checkout = self.reporter.new_checkout();
         self.repo
@@ -123,17 +220,313 @@ impl<R: StatusReporter> Git<R> {
         Ok(())
     }
 
+    /// Pushes main and all the tags, replacing them on the remote.
+    /// The `.git` folder is backed up first, the remote history is lost.
     pub fn push_force(&self) -> Result<(), Error> {
+        let history = backup::history_files(&self.path)?;
+        self.backup(backup::HISTORY_REASON, &history)?;
+
+        let mut refspecs = vec![String::from("+refs/heads/main")];
+        for tag in self.repo.tag_names(None)?.iter().flatten() {
+            refspecs.push(format!("+refs/tags/{}", tag));
+        }
+
         let mut push_opts = self.reporter.new_push_options()?;
         let mut remote = self.repo.find_remote("origin")?;
-        remote.push(&["+refs/heads/main"], Some(&mut push_opts))?;
+        remote.push(&refspecs, Some(&mut push_opts))?;
+        Ok(())
+    }
+
+    /// Host that holds the lock on origin, or None if it's unlocked.
+    /// Only the small lock branch is fetched, not the world.
+    pub fn fetch_lock(&self) -> Result<Option<String>, Error> {
+        let mut remote = self.repo.find_remote("origin")?;
+        let mut fetch_options = self.reporter.new_fetch_options();
+        let refspec = format!("+refs/heads/{0}:refs/remotes/origin/{0}", LOCK_BRANCH);
+        remote
+            .fetch(&[&refspec], Some(&mut fetch_options), None)
+            .map_err(|err| self.reporter.error(err))?;
+
+        let tree = match self
+            .repo
+            .find_branch(&format!("origin/{}", LOCK_BRANCH), BranchType::Remote)
+        {
+            Ok(lock) => lock.get().peel_to_tree()?,
+            Err(err) if err.code() == ErrorCode::NotFound => {
+                // The lock branch is created the first time someone locks the server.
+                // Until then the lock is the file of the world that older versions write,
+                // a host of an older version still has it.
+                let mut fetch_options = self.reporter.new_fetch_options();
+                remote
+                    .fetch(
+                        &["+refs/heads/main:refs/remotes/origin/main"],
+                        Some(&mut fetch_options),
+                        None,
+                    )
+                    .map_err(|err| self.reporter.error(err))?;
+
+                match self.repo.find_branch("origin/main", BranchType::Remote) {
+                    Ok(main) => main.get().peel_to_tree()?,
+                    // An empty origin
+                    Err(err) if err.code() == ErrorCode::NotFound => return Ok(None),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let Some(entry) = tree.get_name(LOCK_FILE) else {
+            return Ok(None);
+        };
+        let blob = entry.to_object(&self.repo)?.peel_to_blob()?;
+        let host = String::from_utf8_lossy(blob.content()).trim().to_string();
+
+        Ok(if host.is_empty() { None } else { Some(host) })
+    }
+
+    /// Sets the host of the lock branch on origin, `None` unlocks it.
+    /// The new lock commit is a child of the one read by `fetch_lock`,
+    /// so the push is rejected if someone else has changed the lock meanwhile.
+    pub fn push_lock(&self, host: Option<&str>) -> Result<(), Error> {
+        let parent = match self
+            .repo
+            .find_branch(&format!("origin/{}", LOCK_BRANCH), BranchType::Remote)
+        {
+            Ok(lock) => Some(lock.get().peel_to_commit()?),
+            Err(err) if err.code() == ErrorCode::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut tree = self.repo.treebuilder(None)?;
+        if let Some(host) = host {
+            tree.insert(LOCK_FILE, self.repo.blob(host.as_bytes())?, 0o100644)?;
+        }
+        let tree = self.repo.find_tree(tree.write()?)?;
+
+        let message = match host {
+            Some(host) => format!("Lock by {}", host),
+            None => "Unlock".into(),
+        };
+        let s = self.signature()?;
+        let parents: Vec<&Commit> = parent.iter().collect();
+        let commit = self.repo.commit(None, &s, &s, &message, &tree, &parents)?;
+
+        let refname = format!("refs/heads/{}", LOCK_BRANCH);
+        self.repo.reference(&refname, commit, true, &message)?;
+
+        let mut push_opts = self.reporter.new_push_options()?;
+        let mut remote = self.repo.find_remote("origin")?;
+        remote.push(&[&refname], Some(&mut push_opts))?;
+        Ok(())
+    }
+
+    /// Deletes the local repo and clones it again,
+    /// so the objects of a pruned history don't take space anymore.
+    /// The files that are different from origin/main are backed up first.
+    pub fn reclone(self) -> Result<Self, Error> {
+        let origin_url = match self.repo.find_remote("origin")?.url() {
+            Some(url) => url.to_string(),
+            None => return Err(Error::from_str("The origin url is not valid utf-8")),
+        };
+
+        {
+            let origin = self.repo.find_branch("origin/main", BranchType::Remote)?;
+            self.backup_changes_from(&origin.get().peel_to_tree()?, "reclone")?;
+        }
+
+        let Self {
+            path,
+            repo,
+            reporter,
+        } = self;
+        drop(repo);
+
+        std::fs::remove_dir_all(&path)?;
+        Self::clone(reporter, path, &origin_url)
+    }
+
+    /// HEAD has commits that origin/main doesn't have.
+    pub fn has_unpushed_snapshots(&self) -> Result<bool, Error> {
+        let origin = self.repo.find_branch("origin/main", BranchType::Remote)?;
+        let origin = origin.get().peel_to_commit()?.id();
+        let head = self.repo.head()?.peel_to_commit()?.id();
+        Ok(head != origin && !self.repo.graph_descendant_of(origin, head)?)
+    }
+
+    /// Files that are different between HEAD and origin/main.
+    pub fn changed_files_from_origin(&self) -> Result<Vec<String>, Error> {
+        let origin = self.repo.find_branch("origin/main", BranchType::Remote)?;
+        let origin_tree = origin.get().peel_to_tree()?;
+        let head_tree = self.repo.head()?.peel_to_tree()?;
+
+        let diff = self
+            .repo
+            .diff_tree_to_tree(Some(&origin_tree), Some(&head_tree), None)?;
+
+        Ok(diff
+            .deltas()
+            .filter_map(|delta| delta.new_file().path().or(delta.old_file().path()))
+            .map(|path| path.to_string_lossy().replace('\\', "/"))
+            .collect())
+    }
+
+    /// Number of commits on main, following the first parents.
+    pub fn history_len(&self) -> Result<usize, Error> {
+        Ok(self.first_parent_history()?.len())
+    }
+
+    /// Disk space used by the `.git` folder.
+    pub fn size(&self) -> Result<u64, Error> {
+        Ok(dir_size(self.repo.path())?)
+    }
+
+    /// Rewrites main to only keep the last `keep` commits and the tagged ones.
+    /// The trees, authors and messages are kept, only the parents change.
+    /// Tags are moved to the rewritten commits,
+    /// it fails if a tag is not on a commit of main because it could not be moved.
+    /// Returns the number of removed commits.
+    pub fn prune_history(&self, keep: usize) -> Result<usize, Error> {
+        let history = self.first_parent_history()?;
+        if history.len() <= keep {
+            return Ok(0);
+        }
+
+        let mut tags: HashMap<Oid, Vec<String>> = HashMap::new();
+        for name in self.repo.tag_names(None)?.iter().flatten() {
+            let reference = self.repo.find_reference(&format!("refs/tags/{}", name))?;
+            let commit = reference.peel_to_commit()?;
+            tags.entry(commit.id()).or_default().push(name.into());
+        }
+
+        // Only the tags of main's snapshots can be moved to the rewritten ones.
+        // Any other is pushed as it is, and would keep the old history on origin.
+        let mut blocking: Vec<&str> = tags
+            .iter()
+            .filter(|(oid, _)| !history.contains(oid))
+            .flat_map(|(_, names)| names.iter().map(String::as_str))
+            .collect();
+        if !blocking.is_empty() {
+            blocking.sort_unstable();
+            return Err(Error::from_str(format!(
+                "These tags are not on a snapshot of main and would keep the old history: {}.\n\
+                Remove them or move them to a snapshot of main before pruning.",
+                blocking.join(", ")
+            )));
+        }
+
+        let kept: Vec<Oid> = history
+            .iter()
+            .enumerate()
+            .filter(|(index, oid)| *index < keep || tags.contains_key(oid))
+            .map(|(_, oid)| *oid)
+            .collect();
+
+        let mut rewritten = HashMap::new();
+        let mut parent: Option<Commit> = None;
+
+        // Oldest first, so every commit can point to the rewritten parent
+        for (index, oid) in kept.iter().rev().enumerate() {
+            self.reporter
+                .status_change("Rewriting history", Some(index as f32 / kept.len() as f32));
+
+            let commit = self.repo.find_commit(*oid)?;
+            let parents: Vec<&Commit> = parent.iter().collect();
+            let new_oid = self.repo.commit(
+                None,
+                &commit.author(),
+                &commit.committer(),
+                commit.message().unwrap_or_default(),
+                &commit.tree()?,
+                &parents,
+            )?;
+
+            rewritten.insert(*oid, new_oid);
+            parent = Some(self.repo.find_commit(new_oid)?);
+        }
+
+        let Some(head) = parent else {
+            return Err(Error::from_str("Can't prune all the history"));
+        };
+        self.repo
+            .reference("refs/heads/main", head.id(), true, "Prune history")?;
+
+        for (oid, names) in tags {
+            let Some(new_oid) = rewritten.get(&oid) else {
+                continue;
+            };
+            let target = self.repo.find_object(*new_oid, None)?;
+
+            for name in names {
+                let old_tag = self
+                    .repo
+                    .find_reference(&format!("refs/tags/{}", name))?
+                    .peel_to_tag();
+
+                match old_tag {
+                    Ok(tag) => {
+                        let tagger = match tag.tagger() {
+                            Some(tagger) => tagger.to_owned(),
+                            None => self.signature()?,
+                        };
+                        let message = tag.message().unwrap_or_default();
+                        self.repo.tag(&name, &target, &tagger, message, true)?;
+                    }
+                    Err(_) => {
+                        self.repo.tag_lightweight(&name, &target, true)?;
+                    }
+                }
+            }
+        }
+
+        Ok(history.len() - kept.len())
+    }
+
+    /// Commits of main, newest first.
+    fn first_parent_history(&self) -> Result<Vec<Oid>, Error> {
+        self.deepen()?;
+        let mut walk = self.repo.revwalk()?;
+        walk.push_ref("refs/heads/main")?;
+        walk.simplify_first_parent()?;
+        Ok(walk.collect::<Result<_, _>>()?)
+    }
+
+    /// Downloads the history that a shallow clone doesn't have.
+    /// The first clone only has the last snapshot, the history is only needed to merge
+    /// diverged snapshots and to read or rewrite the history.
+    fn deepen(&self) -> Result<(), Error> {
+        if !self.repo.is_shallow() {
+            return Ok(());
+        }
+
+        let mut remote = self.repo.find_remote("origin")?;
+        let mut fetch_options = self.reporter.new_fetch_options();
+        fetch_options.depth(UNSHALLOW_DEPTH);
+        remote
+            .fetch(&["main"], Some(&mut fetch_options), None)
+            .map_err(|err| self.reporter.error(err))?;
         Ok(())
     }
 
+    /// Last common snapshot, `None` if the histories don't have any snapshot in common.
+    /// A shallow clone is deepened if it doesn't have it.
+    fn merge_base(&self, one: Oid, two: Oid) -> Result<Option<Oid>, Error> {
+        match self.repo.merge_base(one, two) {
+            Ok(merge_base) => Ok(Some(merge_base)),
+            Err(err) if err.code() == ErrorCode::NotFound && self.repo.is_shallow() => {
+                self.deepen()?;
+                self.merge_base(one, two)
+            }
+            Err(err) if err.code() == ErrorCode::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
     /// Equivelent to: `reset --hard origin/main`
+    /// The files that are different from origin/main are backed up first.
     pub fn reset_hard_to_origin(&self) -> Result<(), Error> {
         let origin = self.repo.find_branch("origin/main", BranchType::Remote)?;
-        let commit = origin.get().peel_to_commit()?.into_object();
+        let commit = origin.get().peel_to_commit()?;
+        self.backup_changes_from(&commit.tree()?, "reset")?;
+        let commit = commit.into_object();
 
         let mut checkout = self.reporter.new_checkout();
         self.repo
@@ -146,7 +539,9 @@ impl<R: StatusReporter> Git<R> {
         let mut remote = self.repo.find_remote("origin")?;
         let mut fetch_options = self.reporter.new_fetch_options();
         fetch_options.download_tags(git2::AutotagOption::All);
-        remote.fetch(&["main"], Some(&mut fetch_options), None)?;
+        remote
+            .fetch(&["main"], Some(&mut fetch_options), None)
+            .map_err(|err| self.reporter.error(err))?;
 
         let fetch_head = self.repo.find_reference("FETCH_HEAD")?;
         let fetch_commit = self.repo.reference_to_annotated_commit(&fetch_head)?;
@@ -193,10 +588,12 @@ impl<R: StatusReporter> Git<R> {
 
             let local = self.repo.find_commit(head_commit.id())?.tree()?;
             let remote = self.repo.find_commit(fetch_commit.id())?.tree()?;
-            let merge_base = self.repo.merge_base(head_commit.id(), fetch_commit.id())?;
+            let Some(merge_base) = self.merge_base(head_commit.id(), fetch_commit.id())? else {
+                return Ok(MergeStatus::HistoryRewritten);
+            };
             let ancestor = self.repo.find_commit(merge_base)?.tree()?;
 
-            let index = self.repo.merge_trees(&ancestor, &local, &remote, None)?;
+            let mut index = self.repo.merge_trees(&ancestor, &local, &remote, None)?;
 
             if index.has_conflicts() {
                 Ok(MergeStatus::Conflicts(
@@ -209,7 +606,25 @@ impl<R: StatusReporter> Git<R> {
                         .collect(),
                 ))
             } else {
-                Ok(MergeStatus::Conflicts(vec![]))
+                // Both sides changed different files, so both changes are kept
+                let tree = self.repo.find_tree(index.write_tree_to(&self.repo)?)?;
+                let signature = self.signature()?;
+                let parents = [
+                    &self.repo.find_commit(head_commit.id())?,
+                    &self.repo.find_commit(fetch_commit.id())?,
+                ];
+                self.repo.commit(
+                    Some("HEAD"),
+                    &signature,
+                    &signature,
+                    "Merge the shared world",
+                    &tree,
+                    &parents,
+                )?;
+
+                let mut checkout = self.reporter.new_checkout();
+                self.repo.checkout_head(Some(checkout.force()))?;
+                Ok(MergeStatus::Ok)
             }
         } else if analysis.0.is_up_to_date() {
             Ok(MergeStatus::Ok)
@@ -235,11 +650,53 @@ impl<R: StatusReporter> Git<R> {
         Ok(())
     }
 
+    /// Backs up the files of the work dir that are different from `tree`,
+    /// including the ones that have not been committed.
+    fn backup_changes_from(&self, tree: &Tree, reason: &str) -> Result<(), Error> {
+        let mut options = DiffOptions::new();
+        options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .include_ignored(true)
+            .recurse_ignored_dirs(true);
+        let diff = self
+            .repo
+            .diff_tree_to_workdir_with_index(Some(tree), Some(&mut options))?;
+
+        let files: Vec<String> = diff
+            .deltas()
+            .filter_map(|delta| delta.new_file().path())
+            .map(|path| path.to_string_lossy().replace('\\', "/"))
+            .collect();
+        self.backup(reason, &files)
+    }
+
+    fn backup(&self, reason: &str, files: &[String]) -> Result<(), Error> {
+        backup::create(&self.path, reason, files, |done, total| {
+            self.reporter.status_count("Backing up", done, total)
+        })?;
+        Ok(())
+    }
+
     pub fn work_dir(&self) -> &PathBuf {
         &self.path
     }
 }
 
+fn dir_size(path: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
 pub fn get_username() -> Result<String, Error> {
     if let Some(username) = Config::open_default()?.get_entry("user.name")?.value() {
         Ok(username.into())
@@ -252,3 +709,239 @@ pub fn get_username() -> Result<String, Error> {
         .into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{TcpListener, TcpStream},
+        process::{Command, Stdio},
+        sync::Barrier,
+        thread,
+    };
+
+    struct NoReporter;
+
+    impl StatusReporter for NoReporter {
+        fn status_change(&self, _operation: &'static str, _progress: Option<f32>) {}
+    }
+
+    /// A bare origin with one snapshot on main, on a new temp folder.
+    fn origin(name: &str) -> (PathBuf, String) {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let origin = dir.join("origin.git");
+        Repository::init_bare(&origin)
+            .unwrap()
+            .set_head("refs/heads/main")
+            .unwrap();
+        let url = format!("file://{}", origin.display());
+
+        let first = Git::new(NoReporter, dir.join("first"), &url).unwrap();
+        std::fs::write(first.work_dir().join("level.dat"), "world").unwrap();
+        first.commit_all("first snapshot").unwrap();
+        first.push().unwrap();
+
+        (dir, url)
+    }
+
+    #[test]
+    fn only_one_contender_takes_the_lock() {
+        let (dir, url) = origin("lock-race");
+        let alice = Git::new(NoReporter, dir.join("alice"), &url).unwrap();
+        let bob = Git::new(NoReporter, dir.join("bob"), &url).unwrap();
+        assert_eq!(alice.fetch_lock().unwrap(), None);
+        assert_eq!(bob.fetch_lock().unwrap(), None);
+
+        // Both push on a lock that they have read as unlocked
+        let start = &Barrier::new(2);
+        let (alice_result, bob_result) = thread::scope(|scope| {
+            let alice = scope.spawn(move || {
+                start.wait();
+                alice.push_lock(Some("alice"))
+            });
+            let bob = scope.spawn(move || {
+                start.wait();
+                bob.push_lock(Some("bob"))
+            });
+            (alice.join().unwrap(), bob.join().unwrap())
+        });
+
+        let (winner, loser) = match (alice_result, bob_result) {
+            (Ok(()), Err(err)) => ("alice", err),
+            (Err(err), Ok(())) => ("bob", err),
+            results => panic!("Exactly one lock must be taken: {:?}", results),
+        };
+        assert!(loser.is_push_rejected(), "{}", loser);
+
+        let checker = Git::new(NoReporter, dir.join("checker"), &url).unwrap();
+        assert_eq!(checker.fetch_lock().unwrap().as_deref(), Some(winner));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn reads_the_lock_of_older_versions_until_the_lock_branch_exists() {
+        let (dir, url) = origin("old-lock");
+        let old_host = Git::new(NoReporter, dir.join("old_host"), &url).unwrap();
+        std::fs::write(old_host.work_dir().join(LOCK_FILE), "old host").unwrap();
+        old_host.commit_all("Lock").unwrap();
+        old_host.push().unwrap();
+
+        let new_host = Git::new(NoReporter, dir.join("new_host"), &url).unwrap();
+        assert_eq!(new_host.fetch_lock().unwrap().as_deref(), Some("old host"));
+
+        new_host.push_lock(None).unwrap();
+        assert_eq!(new_host.fetch_lock().unwrap(), None);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn forced_commit_is_made_without_changes() {
+        let (dir, url) = origin("forced-commit");
+        let host = Git::new(NoReporter, dir.join("host"), &url).unwrap();
+        let head = || host.repo.head().unwrap().peel_to_commit().unwrap();
+        let first = head().id();
+
+        host.commit_all("nothing changed").unwrap();
+        assert_eq!(head().id(), first);
+
+        host.commit_all_forced("Session\n\nStart: 1").unwrap();
+        assert_eq!(head().parent_id(0).unwrap(), first);
+        assert_eq!(head().message(), Some("Session\n\nStart: 1"));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// Serves the repos of `root` over smart http with `git http-backend`,
+    /// libgit2 only makes shallow clones over the network.
+    fn http_server(root: &Path) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let root = root.to_path_buf();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let root = root.clone();
+                thread::spawn(move || serve_git_request(stream.unwrap(), &root));
+            }
+        });
+        url
+    }
+
+    fn serve_git_request(stream: TcpStream, root: &Path) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let mut request = line.split_whitespace();
+        let method = request.next().unwrap().to_string();
+        let target = request.next().unwrap();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let (path, query) = (path.to_string(), query.to_string());
+
+        let mut headers = HashMap::new();
+        loop {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            match line.trim_end().split_once(':') {
+                Some((name, value)) => {
+                    headers.insert(name.to_lowercase(), value.trim().to_string())
+                }
+                None => break,
+            };
+        }
+
+        let mut body = Vec::new();
+        if headers.get("transfer-encoding").map(String::as_str) == Some("chunked") {
+            loop {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                let size =
+                    usize::from_str_radix(line.trim().split(';').next().unwrap(), 16).unwrap();
+                let mut chunk = vec![0; size + 2];
+                reader.read_exact(&mut chunk).unwrap();
+                if size == 0 {
+                    break;
+                }
+                body.extend_from_slice(&chunk[..size]);
+            }
+        } else if let Some(length) = headers.get("content-length") {
+            body.resize(length.parse().unwrap(), 0);
+            reader.read_exact(&mut body).unwrap();
+        }
+
+        let mut backend = Command::new("git")
+            .arg("http-backend")
+            .env("GIT_PROJECT_ROOT", root)
+            .env("GIT_HTTP_EXPORT_ALL", "1")
+            .env("PATH_INFO", path)
+            .env("QUERY_STRING", query)
+            .env("REQUEST_METHOD", method)
+            .env(
+                "CONTENT_TYPE",
+                headers.remove("content-type").unwrap_or_default(),
+            )
+            .env("CONTENT_LENGTH", body.len().to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        backend.stdin.take().unwrap().write_all(&body).unwrap();
+        let output = backend.wait_with_output().unwrap().stdout;
+
+        let split = output.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let mut status = "200 OK".to_string();
+        let mut response = Vec::new();
+        for header in String::from_utf8_lossy(&output[..split]).lines() {
+            match header.strip_prefix("Status:") {
+                Some(value) => status = value.trim().to_string(),
+                None => writeln!(response, "{}\r", header).unwrap(),
+            }
+        }
+        let content = &output[split + 4..];
+
+        let mut stream = stream;
+        write!(stream, "HTTP/1.1 {}\r\n", status).unwrap();
+        stream.write_all(&response).unwrap();
+        write!(
+            stream,
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            content.len()
+        )
+        .unwrap();
+        stream.write_all(content).unwrap();
+    }
+
+    #[test]
+    fn shallow_clone_is_deepened_when_the_history_is_needed() {
+        let (dir, url) = origin("shallow-clone");
+        let old_host = Git::new(NoReporter, dir.join("old_host"), &url).unwrap();
+        std::fs::write(old_host.work_dir().join("level.dat"), "second").unwrap();
+        old_host.commit_all("second snapshot").unwrap();
+        old_host.push().unwrap();
+
+        let http_url = format!("{}/origin.git", http_server(&dir));
+        let host = Git::new(NoReporter, dir.join("host"), &http_url).unwrap();
+        assert!(host.repo.is_shallow());
+
+        // Diverged snapshots are merged on the shallow clone
+        std::fs::write(old_host.work_dir().join("level.dat"), "third").unwrap();
+        old_host.commit_all("third snapshot").unwrap();
+        old_host.push().unwrap();
+        std::fs::write(host.work_dir().join("plugins.txt"), "none").unwrap();
+        host.commit_all("host snapshot").unwrap();
+        assert!(matches!(host.pull().unwrap(), MergeStatus::Ok));
+        assert_eq!(
+            std::fs::read_to_string(host.work_dir().join("level.dat")).unwrap(),
+            "third"
+        );
+
+        // The first snapshot is only downloaded with the whole history
+        assert_eq!(host.first_parent_history().unwrap().len(), 4);
+        assert!(!host.repo.is_shallow());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,192 @@
+use super::{Git, GitStatusReporter, StatusReporter};
+use crate::{backup::free_path, error::Error};
+use git2::{BranchType, ObjectType, Oid, Repository, ResetType};
+use std::{fs, path::PathBuf};
+
+impl<R: StatusReporter> Git<R> {
+    /// Checks that the local repo can be read, and repairs it if it can't:
+    /// - An unreadable index is rebuilt from HEAD.
+    /// - Remote branches that point to nothing are deleted and fetched again.
+    /// - If main is broken but origin/main is not, main is moved to origin/main.
+    ///   The work dir is not touched, so the local changes are committed again later.
+    /// - If the snapshot of origin/main is still missing objects,
+    ///   the local copy is set aside and cloned again.
+    ///
+    /// Only the trees of the last snapshots are checked, reading all the history would be too slow.
+    /// Even so it reads every file of the snapshot, so it's not done each time the repo is opened.
+    pub fn repair(self, origin_url: &str) -> Result<Self, Error> {
+        let damaged = match self.repair_refs() {
+            Ok(()) => self.damaged(),
+            Err(err) if err.is_transient() || err.is_cancelled() || err.is_auth() => {
+                return Err(err)
+            }
+            Err(_) => true,
+        };
+        if !damaged {
+            return Ok(self);
+        }
+
+        let Self {
+            path,
+            repo,
+            reporter,
+        } = self;
+        // The files of the repo can't be moved while they are open
+        drop(repo);
+
+        Self::reclone_aside(reporter, path, origin_url)
+    }
+
+    /// Fixes what can be fixed without losing the local files.
+    fn repair_refs(&self) -> Result<(), Error> {
+        let mut fetch = false;
+        let names: Vec<String> = self
+            .repo
+            .references()?
+            .names()
+            .flatten()
+            .map(Into::into)
+            .collect();
+        for name in names
+            .iter()
+            .filter(|name| name.starts_with("refs/remotes/"))
+        {
+            // Like origin/HEAD, they are fixed with the branch they point to
+            let symbolic = self
+                .repo
+                .find_reference(name)
+                .map(|reference| reference.symbolic_target().is_some())
+                .unwrap_or(false);
+            if !symbolic && self.commit_is_broken(name) {
+                match self.repo.find_reference(name) {
+                    Ok(mut reference) => reference.delete()?,
+                    Err(_) => fs::remove_file(self.repo.path().join(name))?,
+                }
+                fetch = true;
+            }
+        }
+        if fetch || self.commit_is_broken("refs/remotes/origin/main") {
+            self.reporter.status_change("Repairing files", None);
+            let mut remote = self.repo.find_remote("origin")?;
+            let mut fetch_options = self.reporter.new_fetch_options();
+            remote
+                .fetch(&["main"], Some(&mut fetch_options), None)
+                .map_err(|err| self.reporter.error(err))?;
+        }
+
+        if self.commit_is_broken("refs/heads/main") {
+            self.reporter.status_change("Repairing files", None);
+            let origin = self.repo.find_branch("origin/main", BranchType::Remote)?;
+            let origin = origin.get().peel_to_commit()?;
+            self.repo
+                .reference("refs/heads/main", origin.id(), true, "repair")?;
+            self.repo.set_head("refs/heads/main")?;
+            self.rebuild_index()?;
+        } else if self.repo.index().is_err() {
+            self.reporter.status_change("Repairing files", None);
+            self.rebuild_index()?;
+        }
+        Ok(())
+    }
+
+    /// The reference is missing, or its commit or any object of its tree is missing.
+    fn commit_is_broken(&self, name: &str) -> bool {
+        let tree = self
+            .repo
+            .find_reference(name)
+            .and_then(|reference| reference.peel_to_commit())
+            .and_then(|commit| commit.tree());
+        match tree {
+            Ok(tree) => missing_object(&self.repo, tree.id()).is_some(),
+            Err(_) => true,
+        }
+    }
+
+    /// The index is replaced by the tree of HEAD, keeping the files of the work dir.
+    fn rebuild_index(&self) -> Result<(), Error> {
+        match fs::remove_file(self.repo.path().join("index")) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        let head = self.repo.head()?.peel(ObjectType::Commit)?;
+        self.repo.reset(&head, ResetType::Mixed, None)?;
+        Ok(())
+    }
+
+    /// The repo still can't be used after repairing the refs:
+    /// HEAD, the index or an object they point to can't be read.
+    fn damaged(&self) -> bool {
+        let Ok(head) = self.repo.head().and_then(|head| head.peel_to_commit()) else {
+            return true;
+        };
+        let Ok(tree) = head.tree() else {
+            return true;
+        };
+        if missing_object(&self.repo, tree.id()).is_some() {
+            return true;
+        }
+
+        let (Ok(index), Ok(odb)) = (self.repo.index(), self.repo.odb()) else {
+            return true;
+        };
+        index.iter().any(|entry| !odb.exists(entry.id))
+    }
+
+    /// Clones the repo again and moves the local copy to a folder next to it.
+    /// The local changes that were not uploaded are left on that folder.
+    /// The local copy is only moved once the new clone is done.
+    pub(super) fn reclone_aside(
+        reporter: GitStatusReporter<R>,
+        path: PathBuf,
+        origin_url: &str,
+    ) -> Result<Self, Error> {
+        reporter.status_change("Repository damaged, downloading it again", None);
+        let Some(name) = path.file_name() else {
+            return Err(Error::from_str(format!(
+                "Invalid repository path {}",
+                path.display()
+            )));
+        };
+        let name = name.to_string_lossy().to_string();
+
+        // Left by a repair that didn't finish, it only has files from the origin
+        let clone_path = path.with_file_name(format!("{} repairing", name));
+        if clone_path.exists() {
+            fs::remove_dir_all(&clone_path)?;
+        }
+        let Self { reporter, .. } = Self::clone(reporter, &clone_path, origin_url)?;
+
+        let backup = free_path(path.with_file_name(format!("{} damaged", name)));
+        if let Err(err) = fs::rename(&path, &backup) {
+            let _ = fs::remove_dir_all(&clone_path);
+            return Err(err.into());
+        }
+        fs::rename(&clone_path, &path)?;
+
+        Ok(Self {
+            repo: Repository::open(&path)?,
+            path,
+            reporter,
+        })
+    }
+}
+
+/// First object of the tree that is not on the repo, searching recursively.
+fn missing_object(repo: &Repository, tree: Oid) -> Option<Oid> {
+    let Ok(tree) = repo.find_tree(tree) else {
+        return Some(tree);
+    };
+    let odb = repo.odb().ok()?;
+    for entry in tree.iter() {
+        let missing = match entry.kind() {
+            Some(ObjectType::Tree) => missing_object(repo, entry.id()),
+            // Submodules are not on the repo
+            Some(ObjectType::Commit) => None,
+            _ => Some(entry.id()).filter(|id| !odb.exists(*id)),
+        };
+        if missing.is_some() {
+            return missing;
+        }
+    }
+    None
+}
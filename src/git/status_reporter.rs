@@ -1,14 +1,114 @@
-use std::ops::Deref;
+use std::{
+    ops::Deref,
+    time::{Duration, Instant},
+};
 
-use git2::{build::CheckoutBuilder, FetchOptions, PushOptions, RemoteCallbacks};
+use git2::{
+    build::CheckoutBuilder, ErrorClass, ErrorCode, FetchOptions, PushOptions, RemoteCallbacks,
+};
 
 use crate::error::Error;
 
-use super::credentials::create_credentials;
+use super::credentials::CredentialResolver;
+
+/// Statuses that servers give when the remote reference has moved,
+/// or has been created by someone else since it was read.
+const NON_FAST_FORWARD_REASONS: [&str; 4] = [
+    "non-fast-forward",
+    "fetch first",
+    "stale info",
+    "already exists",
+];
+
+/// Speed and estimates are not shown until the operation has run this long,
+/// the first moments are too noisy.
+const MIN_ESTIMATE_TIME: Duration = Duration::from_secs(1);
+
+/// Progress of a download, an upload or a checkout.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransferProgress {
+    /// What is counted, like "objects" or "files".
+    pub unit: &'static str,
+    pub done: usize,
+    pub total: usize,
+    /// Bytes transferred, if the operation moves data.
+    pub bytes: Option<u64>,
+    /// Bytes to transfer, git doesn't know it in advance.
+    pub total_bytes: Option<u64>,
+    /// Time since the operation started.
+    pub elapsed: Duration,
+    /// The operation stops if the reporter is cancelled.
+    /// Uploads and checkouts can't be stopped halfway.
+    pub cancellable: bool,
+}
+
+impl TransferProgress {
+    /// Goes from 0 to 1, by bytes if the total is known.
+    pub fn fraction(&self) -> f32 {
+        match (self.bytes, self.total_bytes) {
+            (Some(bytes), Some(total)) if total > 0 => bytes as f32 / total as f32,
+            _ => self.done as f32 / self.total.max(1) as f32,
+        }
+    }
+
+    /// Average speed since the operation started.
+    pub fn bytes_per_second(&self) -> Option<f64> {
+        match self.bytes {
+            Some(bytes) if self.elapsed >= MIN_ESTIMATE_TIME => {
+                Some(bytes as f64 / self.elapsed.as_secs_f64())
+            }
+            _ => None,
+        }
+    }
+
+    /// Time remaining if it keeps the average speed.
+    pub fn eta(&self) -> Option<Duration> {
+        let fraction = self.fraction() as f64;
+        if fraction <= 0. || self.elapsed < MIN_ESTIMATE_TIME {
+            return None;
+        }
+        Some(self.elapsed.mul_f64((1. - fraction).max(0.) / fraction))
+    }
+}
 
 pub trait StatusReporter {
     /// Progress goes from 0 to 1
     fn status_change(&self, operation: &'static str, progress: Option<f32>);
+
+    /// Progress of an operation that goes through `total` items.
+    fn status_count(&self, operation: &'static str, done: usize, total: usize) {
+        self.status_change(operation, Some(done as f32 / total.max(1) as f32));
+    }
+
+    /// Progress of an operation that moves objects or files.
+    /// By default only the fraction is reported.
+    fn transfer_progress(&self, operation: &'static str, progress: &TransferProgress) {
+        self.status_change(operation, Some(progress.fraction()));
+    }
+
+    /// Polled by cancellable operations, they fail with `Error::cancelled` if it's true.
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+/// Remembers when the current operation started, to compute its speed.
+/// Fetching goes through many operations with the same callback.
+#[derive(Default)]
+struct OperationTimer {
+    current: Option<(&'static str, Instant)>,
+}
+
+impl OperationTimer {
+    fn elapsed(&mut self, operation: &'static str) -> Duration {
+        match self.current {
+            Some((current, started)) if current == operation => started.elapsed(),
+            _ => {
+                self.current = Some((operation, Instant::now()));
+                Duration::ZERO
+            }
+        }
+    }
 }
 
 pub struct GitStatusReporter<R: StatusReporter> {
@@ -33,16 +133,38 @@ impl<R: StatusReporter> GitStatusReporter<R> {
         Self { reporter }
     }
 
+    /// Callbacks make the operation fail when it's cancelled,
+    /// so the error is replaced by `Error::cancelled`.
+    pub fn error(&self, err: git2::Error) -> Error {
+        if self.reporter.is_cancelled() {
+            Error::cancelled()
+        } else {
+            err.into()
+        }
+    }
+
     pub fn new_checkout(&self) -> CheckoutBuilder {
         let mut checkout = CheckoutBuilder::new();
 
-        checkout.progress(|_, curl, total| {
-            const OPERATION: &'static str = "Updating files";
+        let mut timer = OperationTimer::default();
+        checkout.progress(move |_, curl, total| {
+            const OPERATION: &str = "Updating files";
+            let elapsed = timer.elapsed(OPERATION);
             if curl == 0 {
                 self.reporter.status_change(OPERATION, None);
             } else {
-                let progress = curl as f32 / total as f32;
-                self.reporter.status_change(OPERATION, Some(progress));
+                self.reporter.transfer_progress(
+                    OPERATION,
+                    &TransferProgress {
+                        unit: "files",
+                        done: curl,
+                        total,
+                        bytes: None,
+                        total_bytes: None,
+                        elapsed,
+                        cancellable: false,
+                    },
+                );
             }
         });
 
@@ -58,7 +180,31 @@ impl<R: StatusReporter> GitStatusReporter<R> {
     pub fn new_push_options(&self) -> Result<PushOptions, Error> {
         let mut cbs = self.new_remote_callbacks();
 
-        cbs.credentials(|_, _, _| create_credentials());
+        let mut credentials = CredentialResolver::default();
+        cbs.credentials(move |url, username, allowed| credentials.resolve(url, username, allowed));
+
+        // A rejected reference doesn't fail the push by itself,
+        // so the upload is only confirmed if the server accepts every reference.
+        // The server only updates a reference if it still points to the commit we built on,
+        // so a non fast forward rejection means that someone pushed first.
+        cbs.push_update_reference(|reference, status| match status {
+            None => Ok(()),
+            Some(reason) => {
+                let code = if NON_FAST_FORWARD_REASONS
+                    .iter()
+                    .any(|non_ff| reason.contains(non_ff))
+                {
+                    ErrorCode::NotFastForward
+                } else {
+                    ErrorCode::GenericError
+                };
+                Err(git2::Error::new(
+                    code,
+                    ErrorClass::Reference,
+                    format!("The server rejected {}: {}", reference, reason),
+                ))
+            }
+        });
 
         let mut push_options = PushOptions::new();
         push_options.remote_callbacks(cbs);
@@ -68,25 +214,97 @@ impl<R: StatusReporter> GitStatusReporter<R> {
     fn new_remote_callbacks(&self) -> RemoteCallbacks {
         let mut progress_callbacks = RemoteCallbacks::new();
 
-        progress_callbacks.push_transfer_progress(move |current, total, _| {
+        let mut timer = OperationTimer::default();
+        progress_callbacks.pack_progress(move |_, current, total| {
+            const OPERATION: &str = "Packing changes";
+            let elapsed = timer.elapsed(OPERATION);
+            if total != 0 {
+                self.reporter.transfer_progress(
+                    OPERATION,
+                    &TransferProgress {
+                        unit: "objects",
+                        done: current,
+                        total,
+                        bytes: None,
+                        total_bytes: None,
+                        elapsed,
+                        cancellable: false,
+                    },
+                );
+            }
+        });
+
+        let mut timer = OperationTimer::default();
+        progress_callbacks.push_transfer_progress(move |current, total, bytes| {
+            const OPERATION: &str = "Uploading";
+            let elapsed = timer.elapsed(OPERATION);
             if total != 0 {
                 if current == 0 {
-                    self.reporter.status_change("Uploading", None);
+                    self.reporter.status_change(OPERATION, None);
                 } else {
-                    let progress = current as f32 / total as f32;
-                    self.reporter.status_change("Uploading", Some(progress));
+                    self.reporter.transfer_progress(
+                        OPERATION,
+                        &TransferProgress {
+                            unit: "objects",
+                            done: current,
+                            total,
+                            bytes: Some(bytes as u64),
+                            total_bytes: None,
+                            elapsed,
+                            cancellable: false,
+                        },
+                    );
                 }
             }
         });
 
+        let mut timer = OperationTimer::default();
         progress_callbacks.transfer_progress(move |stats| {
-            if stats.total_objects() != 0 {
-                if stats.indexed_objects() == 0 {
-                    self.reporter.status_change("Downloading", None);
-                } else {
-                    let progress = stats.indexed_objects() as f32 / stats.total_objects() as f32;
-                    self.reporter.status_change("Downloading", Some(progress));
-                }
+            // Stopping a fetch doesn't change anything, the objects are only kept when it's done
+            if self.reporter.is_cancelled() {
+                return false;
+            }
+            if stats.total_objects() == 0 {
+                return true;
+            }
+
+            // Objects are received first, then the deltas between them are resolved
+            let (operation, progress) = if stats.received_objects() < stats.total_objects() {
+                const OPERATION: &str = "Downloading";
+                let elapsed = timer.elapsed(OPERATION);
+                (
+                    OPERATION,
+                    TransferProgress {
+                        unit: "objects",
+                        done: stats.received_objects(),
+                        total: stats.total_objects(),
+                        bytes: Some(stats.received_bytes() as u64),
+                        total_bytes: None,
+                        elapsed,
+                        cancellable: true,
+                    },
+                )
+            } else {
+                const OPERATION: &str = "Resolving changes";
+                let elapsed = timer.elapsed(OPERATION);
+                (
+                    OPERATION,
+                    TransferProgress {
+                        unit: "changes",
+                        done: stats.indexed_deltas(),
+                        total: stats.total_deltas(),
+                        bytes: None,
+                        total_bytes: None,
+                        elapsed,
+                        cancellable: true,
+                    },
+                )
+            };
+
+            if progress.done == 0 {
+                self.reporter.status_change(operation, None);
+            } else {
+                self.reporter.transfer_progress(operation, &progress);
             }
             true
         });
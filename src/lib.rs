@@ -0,0 +1,4 @@
+//! Signature checks shared by the app and the `sign` tool.
+
+pub mod manifest;
+pub mod verify_signature;
@@ -2,18 +2,23 @@
 
 mod app;
 mod autoupdate;
+mod backup;
 mod ddns;
 mod error;
 mod fetch;
 mod git;
+mod port_mapping;
 mod process;
 mod public_ip;
-mod verify_signature;
+mod reachability;
+mod server_list_ping;
 mod pull_channel;
+mod world_store;
 
 use app::*;
 use eframe::egui;
 use egui::*;
+use shared_minecraft_server::{manifest, verify_signature};
 
 fn main() {
     autoupdate::update();
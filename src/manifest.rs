@@ -0,0 +1,250 @@
+use crate::verify_signature::{signature_path, to_hex, verify_signature, verify_signature_from};
+use ring::digest::{Context, SHA256};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+/// Signed list with the SHA-256 of every executable file of the server.
+/// Its signature is on `manifest.sha256.ed25519`.
+pub const MANIFEST_LOCAL_PATH: &str = "manifest.sha256";
+
+/// Extensions of the files that can run code on the host machine.
+const EXECUTABLE_EXTENSIONS: [&str; 11] = [
+    "jar", "sh", "bat", "cmd", "ps1", "exe", "dll", "so", "dylib", "jnilib", "py",
+];
+
+/// Folders that the server jar fills on its first run, with the jars it has inside.
+/// They are not checked, their content comes from the server jar, which is.
+const GENERATED_DIRS: [&str; 3] = ["libraries", "versions", "cache"];
+
+pub struct Manifest {
+    /// Local path (with '/' separators) to lowercase hex SHA-256
+    files: HashMap<String, String>,
+}
+
+impl Manifest {
+    /// Hashes every executable file inside the server folder.
+    #[allow(dead_code)] // Only used by the sign tool
+    pub fn from_dir<P: AsRef<Path>>(server_path: P) -> io::Result<Self> {
+        let mut files = HashMap::new();
+        for local_path in executable_files(server_path.as_ref())? {
+            let hash = file_hash(server_path.as_ref().join(&local_path))?;
+            files.insert(to_manifest_path(&local_path), hash);
+        }
+        Ok(Self { files })
+    }
+
+    /// Reads the manifest only if it exists and has a valid signature.
+    pub fn load_verified<P: AsRef<Path>>(server_path: P) -> Option<Self> {
+        let path = server_path.as_ref().join(MANIFEST_LOCAL_PATH);
+        if !verify_signature(&path) {
+            return None;
+        }
+        let content = std::fs::read_to_string(path).ok()?;
+        Some(Self::parse(&content))
+    }
+
+    /// Parses the `sha256sum` format: `<hash>  <path>` per line.
+    pub fn parse(content: &str) -> Self {
+        let files = content
+            .lines()
+            .filter_map(|line| line.split_once("  "))
+            .map(|(hash, path)| (path.trim().to_string(), hash.trim().to_lowercase()))
+            .collect();
+        Self { files }
+    }
+
+    pub fn hash_of(&self, local_path: &Path) -> Option<&str> {
+        self.files
+            .get(&to_manifest_path(local_path))
+            .map(String::as_str)
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut paths: Vec<&String> = self.files.keys().collect();
+        paths.sort();
+        for path in paths {
+            writeln!(f, "{}  {}", self.files[path], path)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    /// The file is not on the manifest and it doesn't have its own signature.
+    Unsigned,
+    /// The file is on the manifest, but with a different hash.
+    Modified,
+}
+
+#[derive(Debug)]
+pub struct UntrustedFile {
+    pub local_path: PathBuf,
+    pub problem: Problem,
+}
+
+impl fmt::Display for UntrustedFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.problem {
+            Problem::Unsigned => write!(f, "Unsigned: {}", self.local_path.display()),
+            Problem::Modified => write!(f, "Modified: {}", self.local_path.display()),
+        }
+    }
+}
+
+/// A file is trusted if the signed manifest lists it with the same hash,
+/// or if it has its own valid `.ed25519` signature.
+/// `local_path` is where the file is listed and signed, `file` is where the content is.
+pub fn check_file<P: AsRef<Path>>(
+    server_path: P,
+    manifest: Option<&Manifest>,
+    local_path: &Path,
+    file: &Path,
+) -> Option<Problem> {
+    let signature = signature_path(server_path.as_ref().join(local_path));
+    if verify_signature_from(file, signature) {
+        return None;
+    }
+
+    match manifest.and_then(|manifest| manifest.hash_of(local_path)) {
+        Some(hash) => match file_hash(file) {
+            Ok(file_hash) if file_hash == hash => None,
+            _ => Some(Problem::Modified),
+        },
+        None => Some(Problem::Unsigned),
+    }
+}
+
+/// Returns every executable file of the server folder that is not trusted.
+/// If the manifest exists but its signature is not valid, the manifest is reported as modified.
+pub fn check<P: AsRef<Path>>(server_path: P) -> io::Result<Vec<UntrustedFile>> {
+    let server_path = server_path.as_ref();
+    let manifest = Manifest::load_verified(server_path);
+    let mut untrusted = vec![];
+
+    if manifest.is_none() && server_path.join(MANIFEST_LOCAL_PATH).exists() {
+        untrusted.push(UntrustedFile {
+            local_path: MANIFEST_LOCAL_PATH.into(),
+            problem: Problem::Modified,
+        });
+    }
+
+    for local_path in executable_files(server_path)? {
+        let file = server_path.join(&local_path);
+        if let Some(problem) = check_file(server_path, manifest.as_ref(), &local_path, &file) {
+            untrusted.push(UntrustedFile {
+                local_path,
+                problem,
+            });
+        }
+    }
+
+    Ok(untrusted)
+}
+
+pub fn is_executable<P: AsRef<Path>>(path: P) -> bool {
+    match path.as_ref().extension() {
+        Some(extension) => {
+            let extension = extension.to_string_lossy().to_lowercase();
+            EXECUTABLE_EXTENSIONS.contains(&extension.as_str())
+        }
+        None => false,
+    }
+}
+
+/// Lists recursively the executable files, relative to the server folder.
+/// The `.git` folder and the folders generated by the server (`GENERATED_DIRS`) are skipped.
+pub fn executable_files<P: AsRef<Path>>(server_path: P) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut pending = vec![PathBuf::new()];
+
+    while let Some(local_dir) = pending.pop() {
+        for entry in std::fs::read_dir(server_path.as_ref().join(&local_dir))? {
+            let entry = entry?;
+            let local_path = local_dir.join(entry.file_name());
+            let file_type = entry.file_type()?;
+
+            if file_type.is_dir() {
+                let generated = local_dir.as_os_str().is_empty()
+                    && GENERATED_DIRS.iter().any(|dir| entry.file_name() == *dir);
+                if entry.file_name() != ".git" && !generated {
+                    pending.push(local_path);
+                }
+            } else if file_type.is_file() && is_executable(&local_path) {
+                files.push(local_path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Lowercase hex SHA-256 of the file content.
+pub fn file_hash<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut context = Context::new(&SHA256);
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buffer[..read]);
+    }
+
+    Ok(to_hex(context.finish().as_ref()))
+}
+
+fn to_manifest_path(local_path: &Path) -> String {
+    local_path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jars_generated_by_the_server_are_not_checked() {
+        let dir = std::env::temp_dir().join(format!("manifest-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for file in [
+            "mc_server.jar",
+            "libraries/com/mojang/brigadier.jar",
+            "versions/1.19.2/server-1.19.2.jar",
+            "cache/mojang_1.19.2.jar",
+            "plugins/libraries/library.jar",
+        ] {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "jar").unwrap();
+        }
+
+        let files = executable_files(&dir).unwrap();
+        let expected: Vec<PathBuf> = vec![
+            "mc_server.jar".into(),
+            Path::new("plugins").join("libraries").join("library.jar"),
+        ];
+        assert_eq!(files, expected);
+
+        let untrusted: Vec<PathBuf> = check(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|file| file.local_path)
+            .collect();
+        assert_eq!(untrusted, expected);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
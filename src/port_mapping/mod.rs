@@ -0,0 +1,142 @@
+mod natpmp;
+mod upnp;
+
+use crate::error::Error;
+use std::{
+    net::{IpAddr, Ipv4Addr, UdpSocket},
+    sync::mpsc::{channel, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// How long the gateway keeps the mapping if it's not renewed.
+pub const LEASE_DURATION: Duration = Duration::from_secs(60 * 60);
+/// Mappings are renewed well before the lease ends.
+pub const RENEW_INTERVAL: Duration = Duration::from_secs(20 * 60);
+
+const DESCRIPTION: &str = "Shared Minecraft Server";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PortMappingStatus {
+    Requesting,
+    Mapped {
+        method: &'static str,
+    },
+    /// The mapping could not be done, port forwarding must be configured by hand.
+    Unavailable(String),
+}
+
+enum Gateway {
+    Upnp(upnp::Igd),
+    NatPmp(Ipv4Addr),
+}
+
+/// A TCP port mapping on the router.
+pub struct PortMapping {
+    gateway: Gateway,
+    port: u16,
+}
+
+impl PortMapping {
+    /// Asks the router to forward the port to this computer.
+    /// UPnP IGD is tried first and NAT-PMP after.
+    pub fn request(port: u16) -> Result<Self, Error> {
+        let upnp_error = match upnp::Igd::discover() {
+            Ok(igd) => match igd.add_port_mapping(port, DESCRIPTION, LEASE_DURATION) {
+                Ok(()) => {
+                    return Ok(Self {
+                        gateway: Gateway::Upnp(igd),
+                        port,
+                    })
+                }
+                Err(err) => err,
+            },
+            Err(err) => err,
+        };
+
+        let natpmp_error = match natpmp::default_gateway() {
+            Ok(gateway) => match natpmp::map_tcp(gateway, port, LEASE_DURATION) {
+                Ok(()) => {
+                    return Ok(Self {
+                        gateway: Gateway::NatPmp(gateway),
+                        port,
+                    })
+                }
+                Err(err) => err,
+            },
+            Err(err) => err,
+        };
+
+        Err(Error::from_str(format!(
+            "UPnP: {}\nNAT-PMP: {}",
+            upnp_error, natpmp_error
+        )))
+    }
+
+    pub fn method(&self) -> &'static str {
+        match self.gateway {
+            Gateway::Upnp(_) => "UPnP",
+            Gateway::NatPmp(_) => "NAT-PMP",
+        }
+    }
+
+    pub fn renew(&self) -> Result<(), Error> {
+        match &self.gateway {
+            Gateway::Upnp(igd) => igd.add_port_mapping(self.port, DESCRIPTION, LEASE_DURATION),
+            Gateway::NatPmp(gateway) => natpmp::map_tcp(*gateway, self.port, LEASE_DURATION),
+        }
+    }
+
+    pub fn remove(&self) -> Result<(), Error> {
+        match &self.gateway {
+            Gateway::Upnp(igd) => igd.delete_port_mapping(self.port),
+            Gateway::NatPmp(gateway) => natpmp::map_tcp(*gateway, self.port, Duration::ZERO),
+        }
+    }
+}
+
+/// Renews the mapping on a background thread.
+/// When the guard is dropped the mapping is removed from the router.
+pub struct PortMappingGuard {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PortMapping {
+    pub fn keep_alive<F>(self, on_renew_error: F) -> PortMappingGuard
+    where
+        F: Fn(Error) + Send + 'static,
+    {
+        let (stop, stop_recv) = channel::<()>();
+
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop_recv.recv_timeout(RENEW_INTERVAL) {
+                if let Err(err) = self.renew() {
+                    on_renew_error(err);
+                }
+            }
+            let _ = self.remove();
+        });
+
+        PortMappingGuard {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for PortMappingGuard {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Local ip used to reach `remote`. No packet is sent.
+fn local_ip_towards(remote: IpAddr) -> Result<IpAddr, Error> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect((remote, 9))?;
+    Ok(socket.local_addr()?.ip())
+}
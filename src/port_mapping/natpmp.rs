@@ -0,0 +1,178 @@
+use crate::{error::Error, process};
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+const NATPMP_PORT: u16 = 5351;
+const MAP_TCP_OPCODE: u8 = 2;
+const FIRST_TIMEOUT: Duration = Duration::from_millis(250);
+const MAX_TRIES: u32 = 4;
+
+/// Reads the default gateway from the routing table.
+pub fn default_gateway() -> Result<Ipv4Addr, Error> {
+    let output = process::run_command(
+        "(Get-NetRoute -DestinationPrefix '0.0.0.0/0' | \
+        Sort-Object RouteMetric | Select-Object -First 1).NextHop",
+    )?;
+
+    match output {
+        Ok(gateway) => gateway
+            .trim()
+            .parse()
+            .map_err(|_| Error::from_str(format!("Invalid default gateway: {:?}", gateway))),
+        Err(err) => Err(Error::from_str(format!(
+            "Could not find default gateway: {}",
+            err
+        ))),
+    }
+}
+
+/// Maps the same external port to this computer.
+/// A lease of zero removes the mapping.
+pub fn map_tcp(gateway: Ipv4Addr, port: u16, lease: Duration) -> Result<(), Error> {
+    map_tcp_at((gateway, NATPMP_PORT).into(), port, lease)
+}
+
+fn map_tcp_at(gateway: SocketAddr, port: u16, lease: Duration) -> Result<(), Error> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(gateway)?;
+
+    let external_port = if lease.is_zero() { 0 } else { port };
+    let lease = u32::try_from(lease.as_secs()).unwrap_or(u32::MAX);
+
+    let mut request = [0; 12];
+    request[1] = MAP_TCP_OPCODE;
+    request[4..6].copy_from_slice(&port.to_be_bytes());
+    request[6..8].copy_from_slice(&external_port.to_be_bytes());
+    request[8..12].copy_from_slice(&lease.to_be_bytes());
+
+    // Retransmissions double the timeout as RFC 6886 says
+    let mut timeout = FIRST_TIMEOUT;
+    let mut response = [0; 16];
+
+    for _ in 0..MAX_TRIES {
+        socket.send(&request)?;
+        socket.set_read_timeout(Some(timeout))?;
+
+        match socket.recv(&mut response) {
+            Ok(16) => return parse_response(&response, external_port),
+            Ok(len) => {
+                return Err(Error::from_str(format!(
+                    "Invalid NAT-PMP response length: {}",
+                    len
+                )))
+            }
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                timeout *= 2;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Err(Error::from_str(
+        "The gateway doesn't answer NAT-PMP requests",
+    ))
+}
+
+fn parse_response(response: &[u8; 16], external_port: u16) -> Result<(), Error> {
+    if response[0] != 0 || response[1] != 128 + MAP_TCP_OPCODE {
+        return Err(Error::from_str("Invalid NAT-PMP response"));
+    }
+
+    match u16::from_be_bytes([response[2], response[3]]) {
+        0 => {}
+        1 => return Err(Error::from_str("NAT-PMP version not supported")),
+        2 => return Err(Error::from_str("The gateway refused the port mapping")),
+        3 => return Err(Error::from_str("The gateway has a network failure")),
+        4 => return Err(Error::from_str("The gateway is out of resources")),
+        code => return Err(Error::from_str(format!("NAT-PMP error code {}", code))),
+    }
+
+    let mapped_port = u16::from_be_bytes([response[10], response[11]]);
+    if external_port != 0 && mapped_port != external_port {
+        return Err(Error::from_str(format!(
+            "The gateway mapped port {} instead of {}",
+            mapped_port, external_port
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Answers one mapping request like a gateway, with `result_code` and `mapped_port`.
+    /// Returns its address and the request that it receives.
+    fn fake_gateway(
+        result_code: u16,
+        mapped_port: u16,
+    ) -> (SocketAddr, thread::JoinHandle<[u8; 12]>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        let thread = thread::spawn(move || {
+            let mut request = [0; 12];
+            let (len, from) = socket.recv_from(&mut request).unwrap();
+            assert_eq!(len, 12);
+
+            let mut response = [0; 16];
+            response[1] = 128 + MAP_TCP_OPCODE;
+            response[2..4].copy_from_slice(&result_code.to_be_bytes());
+            response[8..10].copy_from_slice(&request[4..6]);
+            response[10..12].copy_from_slice(&mapped_port.to_be_bytes());
+            response[12..16].copy_from_slice(&request[8..12]);
+            socket.send_to(&response, from).unwrap();
+            request
+        });
+
+        (addr, thread)
+    }
+
+    #[test]
+    fn maps_the_port() {
+        let (gateway, request) = fake_gateway(0, 25565);
+        map_tcp_at(gateway, 25565, Duration::from_secs(60)).unwrap();
+
+        let request = request.join().unwrap();
+        assert_eq!(request[0], 0);
+        assert_eq!(request[1], MAP_TCP_OPCODE);
+        assert_eq!(u16::from_be_bytes([request[4], request[5]]), 25565);
+        assert_eq!(u16::from_be_bytes([request[6], request[7]]), 25565);
+        assert_eq!(
+            u32::from_be_bytes([request[8], request[9], request[10], request[11]]),
+            60
+        );
+    }
+
+    #[test]
+    fn removes_the_mapping_with_a_lease_of_zero() {
+        let (gateway, request) = fake_gateway(0, 0);
+        map_tcp_at(gateway, 25565, Duration::ZERO).unwrap();
+
+        let request = request.join().unwrap();
+        assert_eq!(u16::from_be_bytes([request[6], request[7]]), 0);
+        assert_eq!(&request[8..12], &[0; 4]);
+    }
+
+    #[test]
+    fn reports_refused_mappings() {
+        let (gateway, _) = fake_gateway(2, 0);
+        let err = map_tcp_at(gateway, 25565, Duration::from_secs(60)).unwrap_err();
+        assert!(format!("{}", err).contains("refused"));
+    }
+
+    #[test]
+    fn rejects_a_different_external_port() {
+        let (gateway, _) = fake_gateway(0, 30000);
+        let err = map_tcp_at(gateway, 25565, Duration::from_secs(60)).unwrap_err();
+        assert!(format!("{}", err).contains("30000"));
+    }
+}
@@ -0,0 +1,421 @@
+use super::local_ip_towards;
+use crate::{error::Error, fetch::fetch_str};
+use http_req::{
+    request::{Method, Request},
+    uri::Uri,
+};
+use std::{
+    net::{IpAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+const SSDP_ADDR: &str = "239.255.255.250:1900";
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(3);
+const SOAP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Error code of the gateways that only accept leases without an end.
+const ONLY_PERMANENT_LEASES_SUPPORTED: &str = "725";
+
+/// Services that can add port mappings, in order of preference.
+const SERVICE_TYPES: [&str; 3] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+/// Error answered by the gateway to a request that it refused.
+struct Fault {
+    /// UPnP error code, empty if the gateway didn't send one.
+    code: String,
+    message: String,
+}
+
+/// UPnP Internet Gateway Device
+pub struct Igd {
+    control_url: String,
+    service_type: &'static str,
+    local_ip: IpAddr,
+}
+
+impl Igd {
+    /// Finds the gateway with a SSDP search and reads its description.
+    pub fn discover() -> Result<Self, Error> {
+        Self::discover_at(SSDP_ADDR)
+    }
+
+    /// Like `discover`, but the search is sent to `ssdp_addr`.
+    fn discover_at(ssdp_addr: &str) -> Result<Self, Error> {
+        let location = search_location(ssdp_addr)?;
+
+        let Some(description) = fetch_str(&location) else {
+            return Err(Error::from_str(format!("Could not read {}", location)));
+        };
+
+        let Some((service_type, control_path)) = find_service(&description) else {
+            return Err(Error::from_str("The gateway doesn't allow port mappings"));
+        };
+
+        let control_url = resolve_url(&location, &control_path);
+        let gateway_ip = Uri::try_from(location.as_str())?
+            .host()
+            .and_then(|host| host.parse().ok())
+            .ok_or_else(|| Error::from_str(format!("Invalid gateway location: {}", location)))?;
+
+        Ok(Self {
+            control_url,
+            service_type,
+            local_ip: local_ip_towards(gateway_ip)?,
+        })
+    }
+
+    /// Gateways that only accept permanent leases get one,
+    /// the mapping is deleted anyway when the server is closed.
+    pub fn add_port_mapping(
+        &self,
+        port: u16,
+        description: &str,
+        lease: Duration,
+    ) -> Result<(), Error> {
+        let arguments = |lease: Duration| {
+            format!(
+                "<NewRemoteHost></NewRemoteHost>\
+                <NewExternalPort>{0}</NewExternalPort>\
+                <NewProtocol>TCP</NewProtocol>\
+                <NewInternalPort>{0}</NewInternalPort>\
+                <NewInternalClient>{1}</NewInternalClient>\
+                <NewEnabled>1</NewEnabled>\
+                <NewPortMappingDescription>{2}</NewPortMappingDescription>\
+                <NewLeaseDuration>{3}</NewLeaseDuration>",
+                port,
+                self.local_ip,
+                description,
+                lease.as_secs(),
+            )
+        };
+        match self.soap_call("AddPortMapping", &arguments(lease))? {
+            Err(fault) if fault.code == ONLY_PERMANENT_LEASES_SUPPORTED && !lease.is_zero() => {
+                self.soap_request("AddPortMapping", &arguments(Duration::ZERO))
+            }
+            result => result.map_err(|fault| Error::from_str(fault.message)),
+        }
+    }
+
+    pub fn delete_port_mapping(&self, port: u16) -> Result<(), Error> {
+        let arguments = format!(
+            "<NewRemoteHost></NewRemoteHost>\
+            <NewExternalPort>{}</NewExternalPort>\
+            <NewProtocol>TCP</NewProtocol>",
+            port
+        );
+        self.soap_request("DeletePortMapping", &arguments)
+    }
+
+    fn soap_request(&self, action: &str, arguments: &str) -> Result<(), Error> {
+        self.soap_call(action, arguments)?
+            .map_err(|fault| Error::from_str(fault.message))
+    }
+
+    /// Sends the request, the outer error is for requests that didn't get an answer.
+    fn soap_call(&self, action: &str, arguments: &str) -> Result<Result<(), Fault>, Error> {
+        let body = format!(
+            "<?xml version=\"1.0\"?>\
+            <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+            s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+            <s:Body><u:{action} xmlns:u=\"{service}\">{arguments}</u:{action}></s:Body>\
+            </s:Envelope>",
+            action = action,
+            service = self.service_type,
+            arguments = arguments,
+        );
+
+        let mut writer = Vec::new();
+        let uri = Uri::try_from(self.control_url.as_str())?;
+        let response = Request::new(&uri)
+            .method(Method::POST)
+            .header("Content-Type", "text/xml; charset=\"utf-8\"")
+            .header(
+                "SOAPAction",
+                &format!("\"{}#{}\"", self.service_type, action),
+            )
+            .header("Content-Length", &body.len())
+            .body(body.as_bytes())
+            .timeout(Some(SOAP_TIMEOUT))
+            .send(&mut writer)?;
+
+        if response.status_code().is_success() {
+            Ok(Ok(()))
+        } else {
+            let body = String::from_utf8_lossy(&writer);
+            let reason = tag_value(&body, "errorDescription").unwrap_or_default();
+            Ok(Err(Fault {
+                code: tag_value(&body, "errorCode").unwrap_or_default(),
+                message: format!(
+                    "{} failed with {}: {}",
+                    action,
+                    response.status_code(),
+                    reason
+                ),
+            }))
+        }
+    }
+}
+
+/// Sends a SSDP M-SEARCH and returns the location of the first gateway that answers.
+fn search_location(ssdp_addr: &str) -> Result<String, Error> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let request = "M-SEARCH * HTTP/1.1\r\n\
+        HOST: 239.255.255.250:1900\r\n\
+        ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+        MAN: \"ssdp:discover\"\r\n\
+        MX: 2\r\n\r\n";
+    socket.send_to(request.as_bytes(), ssdp_addr)?;
+
+    let deadline = Instant::now() + SEARCH_TIMEOUT;
+    let mut buffer = [0; 2048];
+
+    while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+        if timeout.is_zero() {
+            break;
+        }
+        socket.set_read_timeout(Some(timeout))?;
+        let Ok((len, _)) = socket.recv_from(&mut buffer) else {
+            break;
+        };
+
+        let response = String::from_utf8_lossy(&buffer[..len]);
+        let location = response.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            if key.trim().eq_ignore_ascii_case("location") {
+                Some(value.trim().to_string())
+            } else {
+                None
+            }
+        });
+
+        if let Some(location) = location {
+            return Ok(location);
+        }
+    }
+
+    Err(Error::from_str("No UPnP gateway found"))
+}
+
+/// Returns the service type and the control url of the first supported service.
+fn find_service(description: &str) -> Option<(&'static str, String)> {
+    SERVICE_TYPES.into_iter().find_map(|service_type| {
+        let start = description.find(&format!("<serviceType>{}</serviceType>", service_type))?;
+        let service = &description[start..];
+        let service = &service[..service.find("</service>").unwrap_or(service.len())];
+        Some((service_type, tag_value(service, "controlURL")?))
+    })
+}
+
+fn tag_value(xml: &str, tag: &str) -> Option<String> {
+    let open_tag = format!("<{}>", tag);
+    let start = xml.find(&open_tag)? + open_tag.len();
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(xml[start..end].trim().to_string())
+}
+
+/// Resolves a control url relative to the description location.
+fn resolve_url(location: &str, url: &str) -> String {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return url.into();
+    }
+
+    let host_start = location.find("://").map(|i| i + 3).unwrap_or(0);
+    let host_end = location[host_start..]
+        .find('/')
+        .map(|i| host_start + i)
+        .unwrap_or(location.len());
+
+    if url.starts_with('/') {
+        format!("{}{}", &location[..host_end], url)
+    } else {
+        format!("{}/{}", &location[..host_end], url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::mpsc::{channel, Receiver},
+        thread,
+    };
+
+    const DESCRIPTION: &str = "<root><device><serviceList>\
+        <service>\
+        <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>\
+        <controlURL>/ctl/L3F</controlURL>\
+        </service>\
+        <service>\
+        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>\
+        <controlURL>/ctl/IPConn</controlURL>\
+        </service>\
+        </serviceList></device></root>";
+
+    const OK: (&str, &str) = ("200 OK", "");
+    const CONFLICT_FAULT: (&str, &str) = (
+        "500 Internal Server Error",
+        "<s:Envelope><s:Body><s:Fault><detail><UPnPError>\
+        <errorCode>718</errorCode>\
+        <errorDescription>ConflictInMappingEntry</errorDescription>\
+        </UPnPError></detail></s:Fault></s:Body></s:Envelope>",
+    );
+    const ONLY_PERMANENT_FAULT: (&str, &str) = (
+        "500 Internal Server Error",
+        "<s:Envelope><s:Body><s:Fault><detail><UPnPError>\
+        <errorCode>725</errorCode>\
+        <errorDescription>OnlyPermanentLeasesSupported</errorDescription>\
+        </UPnPError></detail></s:Fault></s:Body></s:Envelope>",
+    );
+
+    /// Answers the SSDP search and the HTTP requests like a router does.
+    /// The control url answers with the `soap_answers` in order, and then with the last one.
+    /// Returns the address to search on and the requests that it receives.
+    fn fake_igd(soap_answers: &[(&'static str, &'static str)]) -> (String, Receiver<String>) {
+        let mut soap_answers = soap_answers.to_vec();
+        soap_answers.reverse();
+        let ssdp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let ssdp_addr = ssdp.local_addr().unwrap().to_string();
+        let http = TcpListener::bind("127.0.0.1:0").unwrap();
+        let http_addr = http.local_addr().unwrap();
+        let (requests, received) = channel();
+
+        thread::spawn(move || {
+            let mut buffer = [0; 2048];
+            let (len, from) = ssdp.recv_from(&mut buffer).unwrap();
+            let _ = requests.send(String::from_utf8_lossy(&buffer[..len]).to_string());
+            let response = format!(
+                "HTTP/1.1 200 OK\r\n\
+                ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+                LOCATION: http://{}/description.xml\r\n\r\n",
+                http_addr
+            );
+            ssdp.send_to(response.as_bytes(), from).unwrap();
+
+            for stream in http.incoming() {
+                let mut stream = stream.unwrap();
+                let request = read_request(&mut stream);
+                let (status, body) = if request.starts_with("GET /description.xml") {
+                    ("200 OK", DESCRIPTION)
+                } else if request.starts_with("POST /ctl/IPConn") {
+                    match soap_answers.len() {
+                        0 => ("500 Internal Server Error", ""),
+                        1 => soap_answers[0],
+                        _ => soap_answers.pop().unwrap(),
+                    }
+                } else {
+                    ("404 Not Found", "")
+                };
+                let _ = requests.send(request);
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+
+        (ssdp_addr, received)
+    }
+
+    /// Reads the head and the body of a HTTP request.
+    fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = vec![];
+        let mut buffer = [0; 1024];
+        loop {
+            let len = stream.read(&mut buffer).unwrap();
+            request.extend_from_slice(&buffer[..len]);
+
+            let text = String::from_utf8_lossy(&request).to_string();
+            let Some(head_end) = text.find("\r\n\r\n") else {
+                continue;
+            };
+            let content_length = text[..head_end]
+                .lines()
+                .find_map(|line| {
+                    let (key, value) = line.split_once(':')?;
+                    key.eq_ignore_ascii_case("content-length")
+                        .then(|| value.trim().parse::<usize>().ok())?
+                })
+                .unwrap_or(0);
+            if len == 0 || request.len() >= head_end + 4 + content_length {
+                return text;
+            }
+        }
+    }
+
+    #[test]
+    fn discovers_the_gateway_and_maps_the_port() {
+        let (ssdp_addr, requests) = fake_igd(&[OK]);
+
+        let igd = Igd::discover_at(&ssdp_addr).unwrap();
+        assert_eq!(
+            igd.service_type,
+            "urn:schemas-upnp-org:service:WANIPConnection:1"
+        );
+        assert!(igd.control_url.ends_with("/ctl/IPConn"));
+
+        igd.add_port_mapping(25565, "Test", Duration::from_secs(60))
+            .unwrap();
+
+        let search = requests.recv().unwrap();
+        assert!(search.starts_with("M-SEARCH * HTTP/1.1"));
+        assert!(search.contains("ssdp:discover"));
+        assert!(requests.recv().unwrap().starts_with("GET /description.xml"));
+
+        let soap = requests.recv().unwrap();
+        assert!(soap.contains("WANIPConnection:1#AddPortMapping"));
+        assert!(soap.contains("<NewExternalPort>25565</NewExternalPort>"));
+        assert!(soap.contains("<NewInternalPort>25565</NewInternalPort>"));
+        assert!(soap.contains("<NewInternalClient>127.0.0.1</NewInternalClient>"));
+        assert!(soap.contains("<NewLeaseDuration>60</NewLeaseDuration>"));
+    }
+
+    #[test]
+    fn reports_why_the_gateway_refused_the_mapping() {
+        let (ssdp_addr, _requests) = fake_igd(&[CONFLICT_FAULT]);
+
+        let igd = Igd::discover_at(&ssdp_addr).unwrap();
+        let err = igd
+            .add_port_mapping(25565, "Test", Duration::from_secs(60))
+            .unwrap_err();
+        assert!(format!("{}", err).contains("ConflictInMappingEntry"));
+    }
+
+    #[test]
+    fn asks_for_a_permanent_lease_if_the_gateway_only_accepts_those() {
+        let (ssdp_addr, requests) = fake_igd(&[ONLY_PERMANENT_FAULT, OK]);
+
+        let igd = Igd::discover_at(&ssdp_addr).unwrap();
+        igd.add_port_mapping(25565, "Test", Duration::from_secs(60))
+            .unwrap();
+
+        let soap: Vec<String> = requests.iter().skip(2).take(2).collect();
+        assert!(soap[0].contains("<NewLeaseDuration>60</NewLeaseDuration>"));
+        assert!(soap[1].contains("<NewLeaseDuration>0</NewLeaseDuration>"));
+    }
+
+    #[test]
+    fn resolves_relative_control_urls() {
+        let location = "http://192.168.1.1:5000/rootDesc.xml";
+        assert_eq!(
+            resolve_url(location, "/ctl/IPConn"),
+            "http://192.168.1.1:5000/ctl/IPConn"
+        );
+        assert_eq!(
+            resolve_url(location, "ctl/IPConn"),
+            "http://192.168.1.1:5000/ctl/IPConn"
+        );
+        assert_eq!(
+            resolve_url(location, "http://192.168.1.1:6000/ctl"),
+            "http://192.168.1.1:6000/ctl"
+        );
+    }
+}
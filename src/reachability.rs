@@ -0,0 +1,63 @@
+use crate::{fetch::fetch_str, public_ip};
+use std::{
+    net::{IpAddr, SocketAddr, TcpStream},
+    time::Duration,
+};
+
+/// If set, the port is checked by an external probe instead of connecting to our own public ip.
+/// The probe receives `GET <url>?ip=<public ip>&port=<port>` and must answer `open` or `closed`.
+pub const PROBE_URL_ENV: &str = "MC_HOSTER_PROBE_URL";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reachability {
+    Checking,
+    Reachable,
+    /// Only the probe endpoint can tell it, our own connection could fail by other reasons.
+    Unreachable,
+    /// The check could not be done, with the reason.
+    Unknown(String),
+}
+
+/// Checks if the port can be reached from outside the local network.
+pub fn check(port: u16) -> Reachability {
+    let Some(ip) = public_ip::get() else {
+        return Reachability::Unknown("Could not get public ip".into());
+    };
+    let ip = ip.trim();
+
+    match std::env::var(PROBE_URL_ENV) {
+        Ok(probe_url) if !probe_url.is_empty() => check_with_probe(&probe_url, ip, port),
+        _ => check_with_connect(ip, port),
+    }
+}
+
+fn check_with_probe(probe_url: &str, ip: &str, port: u16) -> Reachability {
+    let separator = if probe_url.contains('?') { '&' } else { '?' };
+    let url = format!("{}{}ip={}&port={}", probe_url, separator, ip, port);
+
+    match fetch_str(&url).as_deref().map(str::trim) {
+        Some("open") => Reachability::Reachable,
+        Some("closed") => Reachability::Unreachable,
+        Some(body) => Reachability::Unknown(format!("Invalid probe response: {:?}", body)),
+        None => Reachability::Unknown("The probe endpoint didn't answer".into()),
+    }
+}
+
+/// Connects to our own public ip.
+/// Some routers don't allow connecting to themselves (no NAT loopback),
+/// so a failure here is not always a closed port, only the probe endpoint can tell it.
+fn check_with_connect(ip: &str, port: u16) -> Reachability {
+    let Ok(ip) = ip.parse::<IpAddr>() else {
+        return Reachability::Unknown(format!("Invalid public ip: {:?}", ip));
+    };
+
+    match TcpStream::connect_timeout(&SocketAddr::new(ip, port), CONNECT_TIMEOUT) {
+        Ok(_) => Reachability::Reachable,
+        Err(err) => Reachability::Unknown(format!(
+            "Could not connect to the public ip, the router may not have NAT loopback: {}",
+            err
+        )),
+    }
+}
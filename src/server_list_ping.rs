@@ -0,0 +1,169 @@
+use crate::error::Error;
+use serde_json::Value;
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+/// Any version can answer a status request.
+const PROTOCOL_VERSION: i32 = -1;
+const STATUS_STATE: i32 = 1;
+const MAX_RESPONSE_LEN: i32 = 1 << 20;
+
+/// What a Minecraft server shows on the multiplayer server list.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerStatus {
+    pub motd: String,
+    pub version: String,
+    pub online_players: u32,
+    pub max_players: u32,
+    /// Some of the connected players, the server chooses which ones.
+    pub sample_players: Vec<String>,
+    pub latency: Duration,
+}
+
+/// Asks the server for its status with the Server List Ping protocol.
+pub fn ping(host: &str, port: u16) -> Result<ServerStatus, Error> {
+    let Some(address) = (host, port).to_socket_addrs()?.next() else {
+        return Err(Error::from_str(format!("Could not resolve {}", host)));
+    };
+
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut handshake = vec![];
+    write_varint(&mut handshake, 0x00);
+    write_varint(&mut handshake, PROTOCOL_VERSION);
+    write_string(&mut handshake, host);
+    handshake.extend_from_slice(&port.to_be_bytes());
+    write_varint(&mut handshake, STATUS_STATE);
+    write_packet(&mut stream, &handshake)?;
+
+    let start = Instant::now();
+    write_packet(&mut stream, &[0x00])?;
+
+    let packet = read_packet(&mut stream)?;
+    let latency = start.elapsed();
+
+    let mut packet = packet.as_slice();
+    if read_varint(&mut packet)? != 0x00 {
+        return Err(Error::from_str("Invalid status response"));
+    }
+    let json_len = read_varint(&mut packet)?;
+    if json_len < 0 || json_len as usize > packet.len() {
+        return Err(Error::from_str("Invalid status response length"));
+    }
+    let json = String::from_utf8_lossy(&packet[..json_len as usize]);
+
+    parse_status(&json, latency)
+}
+
+fn parse_status(json: &str, latency: Duration) -> Result<ServerStatus, Error> {
+    let status: Value = serde_json::from_str(json)
+        .map_err(|err| Error::from_str(format!("Invalid status json: {}", err)))?;
+
+    let players = &status["players"];
+    let sample_players = players["sample"]
+        .as_array()
+        .map(|sample| {
+            sample
+                .iter()
+                .filter_map(|player| player["name"].as_str())
+                .map(strip_formatting)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(ServerStatus {
+        motd: strip_formatting(&chat_text(&status["description"])),
+        version: status["version"]["name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        online_players: players["online"].as_u64().unwrap_or(0) as u32,
+        max_players: players["max"].as_u64().unwrap_or(0) as u32,
+        sample_players,
+        latency,
+    })
+}
+
+/// The motd is either a plain string or a chat component with nested `extra` parts.
+fn chat_text(component: &Value) -> String {
+    match component {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts.iter().map(chat_text).collect(),
+        Value::Object(_) => {
+            let mut text = component["text"].as_str().unwrap_or_default().to_string();
+            if let Some(extra) = component["extra"].as_array() {
+                text.extend(extra.iter().map(chat_text));
+            }
+            text
+        }
+        _ => String::new(),
+    }
+}
+
+/// Removes the `§` color and style codes.
+fn strip_formatting(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+fn write_packet(stream: &mut TcpStream, data: &[u8]) -> Result<(), Error> {
+    let mut packet = vec![];
+    write_varint(&mut packet, data.len() as i32);
+    packet.extend_from_slice(data);
+    stream.write_all(&packet)?;
+    Ok(())
+}
+
+fn read_packet(stream: &mut TcpStream) -> Result<Vec<u8>, Error> {
+    let len = read_varint(stream)?;
+    if len <= 0 || len > MAX_RESPONSE_LEN {
+        return Err(Error::from_str(format!("Invalid packet length: {}", len)));
+    }
+    let mut packet = vec![0; len as usize];
+    stream.read_exact(&mut packet)?;
+    Ok(packet)
+}
+
+fn write_string(buffer: &mut Vec<u8>, text: &str) {
+    write_varint(buffer, text.len() as i32);
+    buffer.extend_from_slice(text.as_bytes());
+}
+
+fn write_varint(buffer: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7F == 0 {
+            buffer.push(value as u8);
+            return;
+        }
+        buffer.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> Result<i32, Error> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7F) as u32) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err(Error::from_str("VarInt is too big"))
+}
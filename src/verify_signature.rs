@@ -1,11 +1,228 @@
-use std::path::{PathBuf, Path};
+use ring::digest::{digest, SHA256};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
 
+/// Root key compiled in the app. It is trusted until a signed trusted keys list replaces it.
 const PUB_KEY: [u8; 32] = [
     84, 173, 250, 169, 229, 119, 126, 227, 207, 177, 135, 154, 158, 77, 116, 125, 197, 204, 135,
     61, 110, 215, 203, 166, 6, 229, 111, 148, 213, 3, 85, 12,
 ];
 
-fn signature_path<P: AsRef<Path>>(file: P) -> PathBuf {
+/// Signed list of trusted keys that lives on the server folder.
+/// It can be signed by any key trusted by the previous list.
+pub const TRUSTED_KEYS_LOCAL_PATH: &str = "trusted_keys.txt";
+
+pub const KEY_ID_LEN: usize = 8;
+const SIGNATURE_LEN: usize = 64;
+
+pub type KeyId = [u8; KEY_ID_LEN];
+
+static TRUSTED_KEYS: RwLock<Option<TrustedKeys>> = RwLock::new(None);
+
+/// First bytes of the SHA-256 of the public key.
+pub fn key_id(public_key: &[u8; 32]) -> KeyId {
+    let mut id = [0; KEY_ID_LEN];
+    id.copy_from_slice(&digest(&SHA256, public_key).as_ref()[..KEY_ID_LEN]);
+    id
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct TrustedKey {
+    pub id: KeyId,
+    pub public_key: [u8; 32],
+}
+
+/// Set of keys that can sign server files.
+///
+/// The file format is one entry per line:
+/// ```text
+/// version=<number, must grow on every update>
+/// key=<hex public key> <owner name, only informative>
+/// revoke=<hex key id>
+/// ```
+#[derive(Clone, Debug)]
+pub struct TrustedKeys {
+    pub version: u64,
+    pub keys: Vec<TrustedKey>,
+    pub revoked: Vec<KeyId>,
+}
+
+impl TrustedKeys {
+    fn builtin() -> Self {
+        Self {
+            version: 0,
+            keys: vec![TrustedKey {
+                id: key_id(&PUB_KEY),
+                public_key: PUB_KEY,
+            }],
+            revoked: vec![],
+        }
+    }
+
+    pub fn parse(content: &str) -> Option<Self> {
+        let mut trusted_keys = Self {
+            version: 0,
+            keys: vec![],
+            revoked: vec![],
+        };
+
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once('=')?;
+            match key.trim() {
+                "version" => trusted_keys.version = value.trim().parse().ok()?,
+                "key" => {
+                    let hex = value.split_whitespace().next()?;
+                    let public_key: [u8; 32] = from_hex(hex)?.try_into().ok()?;
+                    trusted_keys.keys.push(TrustedKey {
+                        id: key_id(&public_key),
+                        public_key,
+                    });
+                }
+                "revoke" => trusted_keys
+                    .revoked
+                    .push(from_hex(value.trim())?.try_into().ok()?),
+                _ => return None,
+            }
+        }
+
+        Some(trusted_keys)
+    }
+
+    pub fn is_revoked(&self, id: &KeyId) -> bool {
+        self.revoked.contains(id)
+    }
+
+    pub fn find(&self, id: &KeyId) -> Option<&TrustedKey> {
+        if self.is_revoked(id) {
+            return None;
+        }
+        self.keys.iter().find(|key| key.id == *id)
+    }
+
+    /// Checks a signature file content against `data`.
+    /// Signatures are `<key id><ed25519 signature>`.
+    /// Signatures without key id are from the builtin key.
+    pub fn verify(&self, data: &[u8], signature_file: &[u8]) -> bool {
+        use ring_compat::signature::{
+            ed25519::{Signature, VerifyingKey},
+            Verifier,
+        };
+
+        let (id, signature) = match signature_file.len() {
+            SIGNATURE_LEN => (key_id(&PUB_KEY), signature_file),
+            len if len == KEY_ID_LEN + SIGNATURE_LEN => {
+                let mut id = [0; KEY_ID_LEN];
+                id.copy_from_slice(&signature_file[..KEY_ID_LEN]);
+                (id, &signature_file[KEY_ID_LEN..])
+            }
+            _ => return false,
+        };
+
+        let Some(trusted_key) = self.find(&id) else {
+            return false;
+        };
+
+        let Ok(key) = VerifyingKey::new(&trusted_key.public_key) else {
+            return false;
+        };
+
+        let Ok(signature) = Signature::from_bytes(signature) else {
+            return false;
+        };
+
+        key.verify(data, &signature).is_ok()
+    }
+}
+
+/// Accepts the trusted keys list of the server folder if it has a bigger version
+/// and it's signed by a key trusted by the current list.
+/// The accepted list is cached on `cache_file`, so an older list can't undo a revocation.
+///
+/// Returns an error if the list of the server folder is newer but not validly signed,
+/// or if the cache can't be read.
+pub fn update_trusted_keys<P: AsRef<Path>, C: AsRef<Path>>(
+    server_path: P,
+    cache_file: C,
+) -> io::Result<()> {
+    let mut current = match std::fs::read_to_string(cache_file.as_ref()) {
+        // Falling back to the builtin list would trust again the keys revoked since then
+        Ok(content) => TrustedKeys::parse(&content).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The cache of trusted keys {} is corrupted",
+                    cache_file.as_ref().display()
+                ),
+            )
+        })?,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => TrustedKeys::builtin(),
+        Err(err) => return Err(err),
+    };
+
+    let list_path = server_path.as_ref().join(TRUSTED_KEYS_LOCAL_PATH);
+    let content = match std::fs::read(&list_path) {
+        Ok(content) => Some(content),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err),
+    };
+
+    if let Some(content) = content {
+        let Some(candidate) = TrustedKeys::parse(&String::from_utf8_lossy(&content)) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} has an invalid format", TRUSTED_KEYS_LOCAL_PATH),
+            ));
+        };
+
+        if candidate.version > current.version {
+            let signature = std::fs::read(signature_path(&list_path)).unwrap_or_default();
+            let mut signer = current.clone();
+            signer.revoked.extend_from_slice(&candidate.revoked);
+
+            if !signer.verify(&content, &signature) {
+                set_trusted_keys(current);
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{} is not signed by a trusted key", TRUSTED_KEYS_LOCAL_PATH),
+                ));
+            }
+
+            std::fs::write(cache_file.as_ref(), &content)?;
+            current = candidate;
+        }
+    }
+
+    set_trusted_keys(current);
+    Ok(())
+}
+
+fn set_trusted_keys(trusted_keys: TrustedKeys) {
+    *TRUSTED_KEYS
+        .write()
+        .expect("Could not write trusted keys cache") = Some(trusted_keys);
+}
+
+pub fn signature_path<P: AsRef<Path>>(file: P) -> PathBuf {
     let mut file_extension = file.as_ref().file_name().unwrap_or_default().to_os_string();
     file_extension.push(".ed25519");
     let mut path = PathBuf::from(file.as_ref());
@@ -14,26 +231,139 @@ fn signature_path<P: AsRef<Path>>(file: P) -> PathBuf {
 }
 
 pub fn verify_signature<P: AsRef<Path>>(file: P) -> bool {
-    use ring_compat::signature::{
-        ed25519::{Signature, VerifyingKey},
-        Verifier,
-    };
-
-    let Ok(key) = VerifyingKey::new(&PUB_KEY) else {
-        return false;
-    };
+    verify_signature_from(file.as_ref(), signature_path(file.as_ref()))
+}
 
+/// Same as `verify_signature` but with an explicit signature file.
+pub fn verify_signature_from<P: AsRef<Path>, S: AsRef<Path>>(file: P, signature: S) -> bool {
     let Ok(file_raw) = std::fs::read(file.as_ref()) else {
         return false;
     };
 
-    let Ok(file_sign_raw) = &std::fs::read(signature_path(file.as_ref())) else {
+    let Ok(file_sign_raw) = std::fs::read(signature.as_ref()) else {
         return false;
     };
 
-    let Ok(signature) = Signature::from_bytes(&file_sign_raw) else {
-        return false;
+    let trusted_keys = TRUSTED_KEYS
+        .read()
+        .expect("Could not read trusted keys cache");
+    match &*trusted_keys {
+        Some(trusted_keys) => trusted_keys.verify(&file_raw, &file_sign_raw),
+        None => TrustedKeys::builtin().verify(&file_raw, &file_sign_raw),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
     };
 
-    key.verify(&file_raw, &signature).is_ok()
+    fn new_key() -> Ed25519KeyPair {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+    }
+
+    fn public_key(key: &Ed25519KeyPair) -> [u8; 32] {
+        key.public_key().as_ref().try_into().unwrap()
+    }
+
+    /// `<key id><signature>`, like the `sign` tool writes them.
+    fn sign(key: &Ed25519KeyPair, data: &[u8]) -> Vec<u8> {
+        let mut signature = key_id(&public_key(key)).to_vec();
+        signature.extend_from_slice(key.sign(data).as_ref());
+        signature
+    }
+
+    fn keys_list(version: u64, keys: &[&Ed25519KeyPair], revoked: &[&Ed25519KeyPair]) -> String {
+        let mut list = format!("version={}\n", version);
+        for key in keys {
+            list += &format!("key={} test\n", to_hex(&public_key(key)));
+        }
+        for key in revoked {
+            list += &format!("revoke={}\n", to_hex(&key_id(&public_key(key))));
+        }
+        list
+    }
+
+    /// A server folder and a cache that trusts `keys`.
+    fn trusting(name: &str, keys: &[&Ed25519KeyPair]) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("server")).unwrap();
+        let cache = dir.join("trusted_keys_cache.txt");
+        std::fs::write(&cache, keys_list(1, keys, &[])).unwrap();
+        (dir, cache)
+    }
+
+    fn publish(dir: &Path, list: &str, signer: Option<&Ed25519KeyPair>) {
+        let path = dir.join("server").join(TRUSTED_KEYS_LOCAL_PATH);
+        std::fs::write(&path, list).unwrap();
+        if let Some(signer) = signer {
+            std::fs::write(signature_path(&path), sign(signer, list.as_bytes())).unwrap();
+        }
+    }
+
+    fn cached(cache: &Path) -> TrustedKeys {
+        TrustedKeys::parse(&std::fs::read_to_string(cache).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn files_signed_by_a_rotated_in_key_are_accepted() {
+        let (old, new) = (new_key(), new_key());
+        let (dir, cache) = trusting("rotated-key", &[&old]);
+        let manifest = b"0123  mc_server.jar\n";
+        assert!(!cached(&cache).verify(manifest, &sign(&new, manifest)));
+
+        publish(&dir, &keys_list(2, &[&old, &new], &[]), Some(&old));
+        update_trusted_keys(dir.join("server"), &cache).unwrap();
+
+        assert_eq!(cached(&cache).version, 2);
+        assert!(cached(&cache).verify(manifest, &sign(&new, manifest)));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn revoked_keys_are_rejected() {
+        let (kept, leaked) = (new_key(), new_key());
+        let (dir, cache) = trusting("revoked-key", &[&kept, &leaked]);
+
+        publish(
+            &dir,
+            &keys_list(2, &[&kept, &leaked], &[&leaked]),
+            Some(&kept),
+        );
+        update_trusted_keys(dir.join("server"), &cache).unwrap();
+        let manifest = b"0123  mc_server.jar\n";
+        assert!(!cached(&cache).verify(manifest, &sign(&leaked, manifest)));
+        assert!(cached(&cache).verify(manifest, &sign(&kept, manifest)));
+
+        // The revoked key can't trust itself again with a newer list
+        publish(&dir, &keys_list(3, &[&leaked], &[]), Some(&leaked));
+        let err = update_trusted_keys(dir.join("server"), &cache).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(cached(&cache).version, 2);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn unsigned_updates_are_refused() {
+        let (trusted, intruder) = (new_key(), new_key());
+        let (dir, cache) = trusting("unsigned-keys", &[&trusted]);
+
+        publish(&dir, &keys_list(2, &[&intruder], &[]), None);
+        let err = update_trusted_keys(dir.join("server"), &cache).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        publish(&dir, &keys_list(2, &[&intruder], &[]), Some(&intruder));
+        let err = update_trusted_keys(dir.join("server"), &cache).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        assert_eq!(cached(&cache).version, 1);
+        let _ = std::fs::remove_dir_all(dir);
+    }
 }
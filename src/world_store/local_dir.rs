@@ -0,0 +1,863 @@
+use super::{MergeStatus, WorldStore};
+use crate::{
+    backup,
+    error::Error,
+    git::{StatusReporter, TransferProgress},
+    manifest::file_hash,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+/// Index of the shared snapshot, on the origin folder.
+const INDEX_FILE: &str = "index.txt";
+/// File contents by their hash, so writing a new index switches the whole snapshot at once.
+const OBJECTS_DIR: &str = "objects";
+/// Local state of the store, inside the work dir.
+pub(super) const STATE_DIR: &str = ".world_store";
+/// Index of the shared snapshot that the local copy is based on.
+const BASE_FILE: &str = "base.txt";
+/// Messages of the local snapshots that are not uploaded yet.
+const PENDING_FILE: &str = "pending.txt";
+/// Version of the lock read by `fetch_lock`.
+const LOCK_BASE_FILE: &str = "lock_base.txt";
+/// Downloaded files waiting to be moved to the local copy.
+const STAGING_DIR: &str = "staging";
+/// Host of the server on the origin folder: the version and the host id on the next line.
+const LOCK_FILE: &str = "lock.txt";
+/// Exists on the origin folder while someone is replacing the index or the lock.
+const INDEX_LOCK_FILE: &str = "index.lock";
+/// Replacing the index takes a moment, an older lock was left by a crashed upload.
+const STALE_LOCK_AGE: Duration = Duration::from_secs(60);
+/// Unused objects are removed once no upload can be still using them.
+const UNUSED_OBJECT_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    hash: String,
+    size: u64,
+    /// Modification time of the local file, so unchanged files are not hashed again.
+    /// It's zero on the shared index.
+    mtime: u128,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Index {
+    version: u64,
+    files: BTreeMap<String, Entry>,
+}
+
+impl Index {
+    fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// ```text
+    /// version=<number>
+    /// <hash> <size> <mtime> <path>
+    /// ```
+    fn parse(content: &str) -> io::Result<Self> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid index line: {:?}", line),
+            )
+        };
+
+        let mut index = Self::default();
+        for line in content.lines().filter(|line| !line.is_empty()) {
+            if let Some(version) = line.strip_prefix("version=") {
+                index.version = version.parse().map_err(|_| invalid(line))?;
+                continue;
+            }
+
+            let mut parts = line.splitn(4, ' ');
+            let (Some(hash), Some(size), Some(mtime), Some(path)) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return Err(invalid(line));
+            };
+
+            index.files.insert(
+                path.into(),
+                Entry {
+                    hash: hash.into(),
+                    size: size.parse().map_err(|_| invalid(line))?,
+                    mtime: mtime.parse().map_err(|_| invalid(line))?,
+                },
+            );
+        }
+        Ok(index)
+    }
+
+    /// Writes a temporal file and renames it, so readers never see half an index.
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let mut content = format!("version={}\n", self.version);
+        for (file, entry) in &self.files {
+            content += &format!("{} {} {} {}\n", entry.hash, entry.size, entry.mtime, file);
+        }
+
+        let temp_path = temp_path(path);
+        fs::write(&temp_path, content)?;
+        fs::rename(temp_path, path)
+    }
+
+    /// Files with a different content on the other index.
+    fn diff(&self, other: &Index) -> BTreeSet<String> {
+        let changed = |a: &Index, b: &Index| {
+            a.files
+                .iter()
+                .filter(|(file, entry)| b.files.get(*file).map(|e| &e.hash) != Some(&entry.hash))
+                .map(|(file, _)| file.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let mut files: BTreeSet<String> = changed(self, other).into_iter().collect();
+        files.extend(changed(other, self));
+        files
+    }
+}
+
+/// Only one client can replace the shared index or the lock at a time,
+/// the others get a `WouldBlock` error and can try again later.
+/// The lock file is removed on drop.
+struct IndexLock {
+    path: PathBuf,
+}
+
+impl IndexLock {
+    fn acquire(origin: &Path) -> Result<Self, Error> {
+        let path = origin.join(INDEX_LOCK_FILE);
+        let mut removed_stale = false;
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(Self { path }),
+                Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    let stale = fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .map(|modified| modified.elapsed().unwrap_or_default() > STALE_LOCK_AGE)
+                        .unwrap_or(false);
+                    if !stale || removed_stale {
+                        return Err(io::Error::new(
+                            io::ErrorKind::WouldBlock,
+                            "Someone else is uploading to the shared folder",
+                        )
+                        .into());
+                    }
+                    let _ = fs::remove_file(&path);
+                    removed_stale = true;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl Drop for IndexLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// World shared on a plain folder, like a network share on a NAS.
+/// The folder has an index of the last snapshot and the contents of its files by hash.
+pub struct LocalDir<R: StatusReporter> {
+    path: PathBuf,
+    origin: PathBuf,
+    reporter: R,
+}
+
+impl<R: StatusReporter> LocalDir<R> {
+    /// It will open the local copy if exists and copy it from the origin if not.
+    pub fn new<P: AsRef<Path>, O: AsRef<Path>>(
+        reporter: R,
+        path: P,
+        origin: O,
+    ) -> Result<Self, Error> {
+        let store = Self {
+            path: path.as_ref().into(),
+            origin: origin.as_ref().into(),
+            reporter,
+        };
+
+        if !store.base_path().exists() {
+            // Don't change anything if the copy can't be made
+            store.load_shared()?;
+
+            // The files that are already there are backed up before being replaced
+            fs::create_dir_all(store.path.join(STATE_DIR))?;
+            if let Err(err) = store.reset_to_shared("clone") {
+                // A half copied world could be opened later as a good one
+                let _ = fs::remove_dir_all(store.path.join(STATE_DIR));
+                return Err(err);
+            }
+        }
+
+        Ok(store)
+    }
+
+    fn base_path(&self) -> PathBuf {
+        self.path.join(STATE_DIR).join(BASE_FILE)
+    }
+
+    fn lock_base_path(&self) -> PathBuf {
+        self.path.join(STATE_DIR).join(LOCK_BASE_FILE)
+    }
+
+    fn pending_path(&self) -> PathBuf {
+        self.path.join(STATE_DIR).join(PENDING_FILE)
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.origin.join(OBJECTS_DIR).join(hash)
+    }
+
+    /// Fails if the share is not reachable, the local copy can still be used without it.
+    fn check_origin(&self) -> io::Result<()> {
+        if self.origin.is_dir() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!(
+                    "The shared folder {} is not available",
+                    self.origin.display()
+                ),
+            ))
+        }
+    }
+
+    fn load_shared(&self) -> io::Result<Index> {
+        self.check_origin()?;
+        Index::load(&self.origin.join(INDEX_FILE))
+    }
+
+    fn has_pending(&self) -> bool {
+        self.pending_path().exists()
+    }
+
+    /// The changes are a pending snapshot until they are pushed,
+    /// only the title of its message is kept.
+    fn commit_changes(&self, message: &str, allow_empty: bool) -> Result<(), Error> {
+        let mut base = Index::load(&self.base_path())?;
+        let changes = self.local_changes(&mut base)?;
+        base.save(&self.base_path())?;
+
+        if allow_empty || !changes.is_empty() {
+            let mut pending = match fs::read_to_string(self.pending_path()) {
+                Ok(pending) => pending,
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+                Err(err) => return Err(err.into()),
+            };
+            pending += message.lines().next().unwrap_or_default();
+            pending.push('\n');
+            fs::write(self.pending_path(), pending)?;
+        }
+        Ok(())
+    }
+
+    /// Local files that are different from `base`, with their new entry or `None` if deleted.
+    /// Files are only hashed if their size or mtime changed.
+    /// The mtime of unchanged files is updated on `base`.
+    fn local_changes(&self, base: &mut Index) -> io::Result<BTreeMap<String, Option<Entry>>> {
+        self.reporter.status_change("Listing changes", None);
+
+        let mut local = vec![];
+        list_files(&self.path, &self.path, &mut local)?;
+
+        let mut to_hash = vec![];
+        for (file, size, mtime) in &local {
+            match base.files.get(file) {
+                Some(entry) if entry.size == *size && entry.mtime == *mtime => {}
+                _ => to_hash.push((file, *size, *mtime)),
+            }
+        }
+
+        let mut changes = BTreeMap::new();
+        for (done, (file, size, mtime)) in to_hash.iter().enumerate() {
+            self.reporter
+                .status_count("Hashing changes", done, to_hash.len());
+
+            let hash = file_hash(self.path.join(file))?;
+            match base.files.get_mut(*file) {
+                Some(entry) if entry.hash == hash => entry.mtime = *mtime,
+                _ => {
+                    let entry = Entry {
+                        hash,
+                        size: *size,
+                        mtime: *mtime,
+                    };
+                    changes.insert(file.to_string(), Some(entry));
+                }
+            }
+        }
+
+        let local: BTreeSet<&String> = local.iter().map(|(file, _, _)| file).collect();
+        for file in base.files.keys() {
+            if !local.contains(file) {
+                changes.insert(file.clone(), None);
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Copies the listed files from the shared snapshot, deleting the ones that it doesn't have.
+    /// Then the shared snapshot becomes the base.
+    fn checkout(&self, shared: &Index, files: &BTreeSet<String>) -> Result<(), Error> {
+        self.replace_files(shared, files)?;
+
+        let mut base = shared.clone();
+        for (file, entry) in base.files.iter_mut() {
+            if let Ok(metadata) = fs::metadata(self.path.join(file)) {
+                entry.mtime = mtime(&metadata);
+            }
+        }
+        base.save(&self.base_path())?;
+
+        match fs::remove_file(self.pending_path()) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Copies the listed files from the shared snapshot, which must not have local changes.
+    /// The shared snapshot becomes the base and the local snapshots stay pending,
+    /// so they are uploaded on top of it.
+    fn merge(&self, shared: &Index, base: &Index, files: &BTreeSet<String>) -> Result<(), Error> {
+        self.replace_files(shared, files)?;
+
+        let mut new_base = shared.clone();
+        for (file, entry) in new_base.files.iter_mut() {
+            if files.contains(file) {
+                if let Ok(metadata) = fs::metadata(self.path.join(file)) {
+                    entry.mtime = mtime(&metadata);
+                }
+            } else if let Some(old_entry) = base.files.get(file) {
+                // Keeps the local changes of the file visible to `local_changes`
+                entry.mtime = old_entry.mtime;
+            }
+        }
+        new_base.save(&self.base_path())?;
+        Ok(())
+    }
+
+    /// Writes the listed files of the shared snapshot on the local copy,
+    /// deleting the ones that it doesn't have.
+    /// The files are downloaded to a staging folder first,
+    /// so a cancelled or failed download doesn't change the local copy.
+    fn replace_files(&self, shared: &Index, files: &BTreeSet<String>) -> Result<(), Error> {
+        let staging = self.path.join(STATE_DIR).join(STAGING_DIR);
+        if let Err(err) = self.stage(shared, files, &staging) {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(err);
+        }
+
+        for (index, file) in files.iter().enumerate() {
+            let local_path = self.path.join(file);
+            match shared.files.get(file) {
+                Some(_) => {
+                    if let Some(parent) = local_path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::rename(staging.join(index.to_string()), &local_path)?;
+                }
+                None => match fs::remove_file(&local_path) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                    _ => {}
+                },
+            }
+        }
+        fs::remove_dir_all(&staging)?;
+        Ok(())
+    }
+
+    /// Downloads the files of `shared` to `staging`, named by their position on `files`.
+    fn stage(&self, shared: &Index, files: &BTreeSet<String>, staging: &Path) -> Result<(), Error> {
+        if staging.exists() {
+            fs::remove_dir_all(staging)?;
+        }
+        fs::create_dir_all(staging)?;
+
+        let started = Instant::now();
+        let total_bytes = files
+            .iter()
+            .filter_map(|file| shared.files.get(file))
+            .map(|entry| entry.size)
+            .sum();
+        let mut bytes = 0;
+
+        for (index, file) in files.iter().enumerate() {
+            if self.reporter.is_cancelled() {
+                return Err(Error::cancelled());
+            }
+            self.reporter.transfer_progress(
+                "Downloading",
+                &TransferProgress {
+                    unit: "files",
+                    done: index,
+                    total: files.len(),
+                    bytes: Some(bytes),
+                    total_bytes: Some(total_bytes),
+                    elapsed: started.elapsed(),
+                    cancellable: true,
+                },
+            );
+
+            if let Some(entry) = shared.files.get(file) {
+                let staged_path = staging.join(index.to_string());
+                fs::copy(self.object_path(&entry.hash), staged_path)?;
+                bytes += entry.size;
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the local copy equal to the shared snapshot.
+    /// The files that are replaced are backed up first, with `reason`.
+    fn reset_to_shared(&self, reason: &str) -> Result<(), Error> {
+        let shared = self.load_shared()?;
+        let mut base = Index::load(&self.base_path())?;
+
+        let mut files = base.diff(&shared);
+        files.extend(self.local_changes(&mut base)?.into_keys());
+
+        self.backup(reason, &files.iter().cloned().collect::<Vec<_>>())?;
+        self.checkout(&shared, &files)
+    }
+
+    fn backup(&self, reason: &str, files: &[String]) -> Result<(), Error> {
+        backup::create(&self.path, reason, files, |done, total| {
+            self.reporter.status_count("Backing up", done, total)
+        })?;
+        Ok(())
+    }
+
+    /// Reads the version and the host of the lock.
+    fn load_lock(&self) -> Result<(u64, Option<String>), Error> {
+        self.check_origin()?;
+        let content = match fs::read_to_string(self.origin.join(LOCK_FILE)) {
+            Ok(content) => content,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok((0, None)),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut lines = content.lines();
+        let version = lines
+            .next()
+            .and_then(|line| line.strip_prefix("version="))
+            .and_then(|version| version.parse().ok())
+            .ok_or_else(|| Error::from_str(format!("Invalid lock file: {:?}", content)))?;
+        let host = lines
+            .next()
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+            .map(String::from);
+
+        Ok((version, host))
+    }
+
+    /// Deletes the contents that the shared index doesn't use anymore.
+    /// Recent objects are kept, they may belong to an upload that has not written the index yet.
+    fn remove_unused_objects(&self, shared: &Index) -> io::Result<()> {
+        let used: BTreeSet<&String> = shared.files.values().map(|entry| &entry.hash).collect();
+        for object in fs::read_dir(self.origin.join(OBJECTS_DIR))? {
+            let object = object?;
+            let recent = object
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .map(|modified| modified.elapsed().unwrap_or_default() < UNUSED_OBJECT_AGE)
+                .unwrap_or(true);
+            if !recent && !used.contains(&object.file_name().to_string_lossy().to_string()) {
+                // Someone may be reading it, it will be removed on the next upload
+                let _ = fs::remove_file(object.path());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: StatusReporter + 'static> WorldStore for LocalDir<R> {
+    fn work_dir(&self) -> &PathBuf {
+        &self.path
+    }
+
+    fn commit_all(&self, message: &str) -> Result<(), Error> {
+        self.commit_changes(message, false)
+    }
+
+    fn commit_all_forced(&self, message: &str) -> Result<(), Error> {
+        self.commit_changes(message, true)
+    }
+
+    fn pull(&self) -> Result<MergeStatus, Error> {
+        let shared = self.load_shared()?;
+        let mut base = Index::load(&self.base_path())?;
+
+        if shared.version == base.version {
+            return Ok(MergeStatus::Ok);
+        }
+
+        let shared_changes = base.diff(&shared);
+
+        if self.has_pending() {
+            let local_changes = self.local_changes(&mut base)?;
+            let conflicts: Vec<String> = shared_changes
+                .iter()
+                .filter(|file| local_changes.contains_key(*file))
+                .cloned()
+                .collect();
+            if !conflicts.is_empty() {
+                return Ok(MergeStatus::Conflicts(conflicts));
+            }
+
+            self.merge(&shared, &base, &shared_changes)?;
+            Ok(MergeStatus::Ok)
+        } else {
+            self.checkout(&shared, &shared_changes)?;
+            Ok(MergeStatus::Ok)
+        }
+    }
+
+    fn push(&self) -> Result<(), Error> {
+        if !self.has_pending() {
+            return Ok(());
+        }
+
+        let shared = self.load_shared()?;
+        let mut base = Index::load(&self.base_path())?;
+
+        if shared.version != base.version {
+            return Err(Error::rejected(
+                "The shared folder has a newer snapshot, it must be synced before uploading",
+            ));
+        }
+
+        let changes = self.local_changes(&mut base)?;
+        let total = changes.len();
+        fs::create_dir_all(self.origin.join(OBJECTS_DIR))?;
+
+        let started = Instant::now();
+        let total_bytes = changes.values().flatten().map(|entry| entry.size).sum();
+        let mut bytes = 0;
+
+        for (done, (file, entry)) in changes.into_iter().enumerate() {
+            self.reporter.transfer_progress(
+                "Uploading",
+                &TransferProgress {
+                    unit: "files",
+                    done,
+                    total,
+                    bytes: Some(bytes),
+                    total_bytes: Some(total_bytes),
+                    elapsed: started.elapsed(),
+                    cancellable: false,
+                },
+            );
+
+            match entry {
+                Some(entry) => {
+                    let object_path = self.object_path(&entry.hash);
+                    if !object_path.exists() {
+                        let temp_path = temp_path(&object_path);
+                        fs::copy(self.path.join(&file), &temp_path)?;
+                        fs::rename(temp_path, object_path)?;
+                    }
+                    bytes += entry.size;
+                    base.files.insert(file, entry);
+                }
+                None => {
+                    base.files.remove(&file);
+                }
+            }
+        }
+
+        base.version = shared.version + 1;
+
+        let mut new_shared = base.clone();
+        for entry in new_shared.files.values_mut() {
+            entry.mtime = 0;
+        }
+
+        {
+            // Someone may have uploaded while the objects were copied,
+            // the index is only replaced if it's still the one the upload is based on.
+            let _lock = IndexLock::acquire(&self.origin)?;
+            if self.load_shared()?.version != shared.version {
+                return Err(Error::rejected(
+                    "Someone else uploaded to the shared folder at the same time",
+                ));
+            }
+            new_shared.save(&self.origin.join(INDEX_FILE))?;
+        }
+        base.save(&self.base_path())?;
+        fs::remove_file(self.pending_path())?;
+
+        self.remove_unused_objects(&new_shared)?;
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<(), Error> {
+        self.reset_to_shared("reset")
+    }
+
+    fn has_unpushed_snapshots(&self) -> Result<bool, Error> {
+        Ok(self.has_pending())
+    }
+
+    fn fetch_lock(&self) -> Result<Option<String>, Error> {
+        let (version, host) = self.load_lock()?;
+        fs::write(self.lock_base_path(), version.to_string())?;
+        Ok(host)
+    }
+
+    fn push_lock(&self, host: Option<&str>) -> Result<(), Error> {
+        let _lock = IndexLock::acquire(&self.origin)?;
+
+        let (version, _) = self.load_lock()?;
+        let base_version = fs::read_to_string(self.lock_base_path())
+            .ok()
+            .and_then(|base| base.trim().parse().ok());
+        if base_version != Some(version) {
+            return Err(Error::rejected("Someone else has changed the lock"));
+        }
+
+        let path = self.origin.join(LOCK_FILE);
+        let temp_path = temp_path(&path);
+        fs::write(
+            &temp_path,
+            format!("version={}\n{}\n", version + 1, host.unwrap_or_default()),
+        )?;
+        fs::rename(temp_path, path)?;
+
+        fs::write(self.lock_base_path(), (version + 1).to_string())?;
+        Ok(())
+    }
+
+    fn changed_files_from_origin(&self) -> Result<Vec<String>, Error> {
+        let shared = self.load_shared()?;
+        let mut base = Index::load(&self.base_path())?;
+
+        let mut files = base.diff(&shared);
+        files.extend(self.local_changes(&mut base)?.into_keys());
+
+        Ok(files.into_iter().collect())
+    }
+
+    fn reclone(self: Box<Self>) -> Result<Box<dyn WorldStore>, Error> {
+        self.backup("reclone", &self.changed_files_from_origin()?)?;
+
+        let Self {
+            path,
+            origin,
+            reporter,
+        } = *self;
+
+        fs::remove_dir_all(&path)?;
+        Ok(Box::new(Self::new(reporter, path, origin)?))
+    }
+}
+
+/// Files of the work dir as `(path, size, mtime)`, the paths with `/`.
+fn list_files(root: &Path, dir: &Path, files: &mut Vec<(String, u64, u128)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+
+        if metadata.is_dir() {
+            if dir == root && (entry.file_name() == STATE_DIR || entry.file_name() == ".git") {
+                continue;
+            }
+            list_files(root, &path, files)?;
+        } else if let Ok(local_path) = path.strip_prefix(root) {
+            let local_path = local_path.to_string_lossy().replace('\\', "/");
+            files.push((local_path, metadata.len(), mtime(&metadata)));
+        }
+    }
+    Ok(())
+}
+
+fn mtime(metadata: &fs::Metadata) -> u128 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|time| time.as_nanos())
+        .unwrap_or(0)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Barrier, thread};
+
+    struct NoReporter;
+
+    impl StatusReporter for NoReporter {
+        fn status_change(&self, _operation: &'static str, _progress: Option<f32>) {}
+    }
+
+    /// A shared folder with one snapshot, on a new temp folder.
+    fn origin(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let origin = dir.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+
+        let first = LocalDir::new(NoReporter, dir.join("first"), &origin).unwrap();
+        fs::write(first.work_dir().join("level.dat"), "world").unwrap();
+        first.commit_all("first snapshot").unwrap();
+        first.push().unwrap();
+
+        (dir, origin)
+    }
+
+    fn read(store: &LocalDir<NoReporter>, file: &str) -> String {
+        fs::read_to_string(store.work_dir().join(file)).unwrap()
+    }
+
+    #[test]
+    fn only_one_contender_takes_the_lock() {
+        let (dir, origin) = origin("local-lock-race");
+        let alice = LocalDir::new(NoReporter, dir.join("alice"), &origin).unwrap();
+        let bob = LocalDir::new(NoReporter, dir.join("bob"), &origin).unwrap();
+        assert_eq!(alice.fetch_lock().unwrap(), None);
+        assert_eq!(bob.fetch_lock().unwrap(), None);
+
+        // Both push on a lock that they have read as unlocked
+        let start = &Barrier::new(2);
+        let (alice_result, bob_result) = thread::scope(|scope| {
+            let alice = scope.spawn(|| {
+                start.wait();
+                alice.push_lock(Some("alice"))
+            });
+            let bob = scope.spawn(|| {
+                start.wait();
+                bob.push_lock(Some("bob"))
+            });
+            (alice.join().unwrap(), bob.join().unwrap())
+        });
+
+        let (winner, loser, mut loser_error) = match (alice_result, bob_result) {
+            (Ok(()), Err(err)) => ("alice", &bob, err),
+            (Err(err), Ok(())) => ("bob", &alice, err),
+            results => panic!("Exactly one lock must be taken: {:?}", results),
+        };
+        // The loser may find the index locked, trying again it must see that the lock was taken
+        if loser_error.is_transient() {
+            loser_error = loser.push_lock(Some("loser")).unwrap_err();
+        }
+        assert!(loser_error.is_push_rejected(), "{}", loser_error);
+
+        let checker = LocalDir::new(NoReporter, dir.join("checker"), &origin).unwrap();
+        assert_eq!(checker.fetch_lock().unwrap().as_deref(), Some(winner));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn push_is_rejected_until_the_newer_snapshot_is_merged() {
+        let (dir, origin) = origin("local-push-conflict");
+        let alice = LocalDir::new(NoReporter, dir.join("alice"), &origin).unwrap();
+        let bob = LocalDir::new(NoReporter, dir.join("bob"), &origin).unwrap();
+
+        fs::write(alice.work_dir().join("level.dat"), "alice world").unwrap();
+        alice.commit_all("alice session").unwrap();
+        alice.push().unwrap();
+
+        fs::write(bob.work_dir().join("bob.txt"), "bob notes").unwrap();
+        bob.commit_all("bob session").unwrap();
+        let err = bob.push().unwrap_err();
+        assert!(err.is_push_rejected(), "{}", err);
+
+        // Different files are merged and the local snapshot stays pending
+        assert!(matches!(bob.pull().unwrap(), MergeStatus::Ok));
+        assert_eq!(read(&bob, "level.dat"), "alice world");
+        assert!(bob.has_unpushed_snapshots().unwrap());
+        bob.push().unwrap();
+
+        let checker = LocalDir::new(NoReporter, dir.join("checker"), &origin).unwrap();
+        assert_eq!(read(&checker, "level.dat"), "alice world");
+        assert_eq!(read(&checker, "bob.txt"), "bob notes");
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn conflicting_changes_are_dropped_on_reset() {
+        let (dir, origin) = origin("local-reset");
+        let alice = LocalDir::new(NoReporter, dir.join("alice"), &origin).unwrap();
+        let bob = LocalDir::new(NoReporter, dir.join("bob"), &origin).unwrap();
+
+        fs::write(alice.work_dir().join("level.dat"), "alice world").unwrap();
+        alice.commit_all("alice session").unwrap();
+        alice.push().unwrap();
+
+        fs::write(bob.work_dir().join("level.dat"), "bob world").unwrap();
+        bob.commit_all("bob session").unwrap();
+        match bob.pull().unwrap() {
+            MergeStatus::Conflicts(conflicts) => assert_eq!(conflicts, ["level.dat"]),
+            _ => panic!("Both changed level.dat"),
+        }
+
+        bob.reset().unwrap();
+        assert_eq!(read(&bob, "level.dat"), "alice world");
+        assert!(!bob.has_unpushed_snapshots().unwrap());
+
+        let backups = backup::list(bob.work_dir()).unwrap();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].reason, "reset");
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn only_old_unused_objects_are_removed() {
+        let (dir, origin) = origin("local-prune");
+        let host = LocalDir::new(NoReporter, dir.join("host"), &origin).unwrap();
+        let objects = || fs::read_dir(origin.join(OBJECTS_DIR)).unwrap().count();
+
+        // A recent object may belong to an upload that has not written the index yet
+        fs::write(host.work_dir().join("level.dat"), "second").unwrap();
+        fs::write(host.work_dir().join("server.properties"), "pvp=true").unwrap();
+        host.commit_all("second snapshot").unwrap();
+        host.push().unwrap();
+        assert_eq!(objects(), 3);
+
+        let old = std::time::SystemTime::now() - UNUSED_OBJECT_AGE * 2;
+        for object in fs::read_dir(origin.join(OBJECTS_DIR)).unwrap() {
+            let object = fs::File::options().write(true).open(object.unwrap().path());
+            object.unwrap().set_modified(old).unwrap();
+        }
+
+        fs::write(host.work_dir().join("level.dat"), "third").unwrap();
+        host.commit_all("third snapshot").unwrap();
+        host.push().unwrap();
+        assert_eq!(objects(), 2);
+
+        let checker = LocalDir::new(NoReporter, dir.join("checker"), &origin).unwrap();
+        assert_eq!(read(&checker, "level.dat"), "third");
+        assert_eq!(read(&checker, "server.properties"), "pvp=true");
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
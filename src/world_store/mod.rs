@@ -0,0 +1,212 @@
+mod local_dir;
+
+use crate::{
+    backup,
+    error::Error,
+    git::{Git, StatusReporter},
+};
+pub use local_dir::LocalDir;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub enum MergeStatus {
+    Ok,
+    /// Both sides changed the listed files, nothing has been merged.
+    Conflicts(Vec<String>),
+    /// The local and the remote histories don't have any commit in common,
+    /// the remote history has been pruned.
+    HistoryRewritten,
+}
+
+/// Where the world is shared between the members of the server.
+/// The local copy lives on `work_dir` and is synced with the shared one (the origin).
+pub trait WorldStore {
+    /// Folder with the local copy of the world.
+    fn work_dir(&self) -> &PathBuf;
+
+    /// Saves the local changes as a snapshot.
+    /// If there aren't changes, it will not commit.
+    fn commit_all(&self, message: &str) -> Result<(), Error>;
+
+    /// Saves the local changes as a snapshot, even if there aren't changes.
+    /// For snapshots that carry something on their message, like a hosted session.
+    fn commit_all_forced(&self, message: &str) -> Result<(), Error>;
+
+    /// Brings the shared snapshot and merges the local snapshots with it,
+    /// unless both have changed the same files.
+    fn pull(&self) -> Result<MergeStatus, Error>;
+
+    /// Uploads the local snapshots.
+    fn push(&self) -> Result<(), Error>;
+
+    /// Makes the local copy equal to the shared snapshot,
+    /// dropping the local snapshots that have not been uploaded.
+    /// The files that are replaced are backed up first.
+    fn reset(&self) -> Result<(), Error>;
+
+    /// There are local snapshots that have not been uploaded.
+    fn has_unpushed_snapshots(&self) -> Result<bool, Error>;
+
+    /// Host that holds the lock on the origin, or None if it's unlocked.
+    /// The lock is kept apart from the world, so reading it is fast.
+    fn fetch_lock(&self) -> Result<Option<String>, Error>;
+
+    /// Sets the host of the lock on the origin, `None` unlocks it.
+    /// It's rejected if the lock has changed since the last `fetch_lock`.
+    fn push_lock(&self, host: Option<&str>) -> Result<(), Error>;
+
+    /// Files that are different between the local and the shared snapshot.
+    fn changed_files_from_origin(&self) -> Result<Vec<String>, Error>;
+
+    /// Deletes the local copy and gets it again from the origin.
+    fn reclone(self: Box<Self>) -> Result<Box<dyn WorldStore>, Error>;
+}
+
+/// Where a server profile shares its world.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StoreLocation {
+    /// A git repository, like the one on GitHub.
+    Git { url: String },
+    /// A plain folder, for example a network share on a NAS.
+    Folder { path: PathBuf },
+}
+
+impl StoreLocation {
+    /// Reads a server profile:
+    /// ```text
+    /// store=git
+    /// url=https://github.com/user/world
+    /// ```
+    /// or
+    /// ```text
+    /// store=folder
+    /// path=\\nas\minecraft
+    /// ```
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        let mut store = None;
+        let mut url = None;
+        let mut path = None;
+
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some(("store", value)) => store = Some(value.trim()),
+                Some(("url", value)) => url = Some(value.trim()),
+                Some(("path", value)) => path = Some(value.trim()),
+                _ => return Err(Error::from_str(format!("Invalid profile line: {}", line))),
+            }
+        }
+
+        match (store, url, path) {
+            (Some("git"), Some(url), _) => Ok(Self::Git { url: url.into() }),
+            (Some("folder"), _, Some(path)) => Ok(Self::Folder { path: path.into() }),
+            _ => Err(Error::from_str(
+                "The profile needs store=git with an url or store=folder with a path",
+            )),
+        }
+    }
+}
+
+/// Opens the local copy of the world, or creates it from the origin.
+pub fn open<R, P>(
+    reporter: R,
+    path: P,
+    location: &StoreLocation,
+) -> Result<Box<dyn WorldStore>, Error>
+where
+    R: StatusReporter + 'static,
+    P: AsRef<Path>,
+{
+    match location {
+        StoreLocation::Git { url } => Ok(Box::new(Git::new(reporter, path, url)?)),
+        StoreLocation::Folder { path: origin } => {
+            Ok(Box::new(LocalDir::new(reporter, path, origin)?))
+        }
+    }
+}
+
+/// Like `open`, but a damaged local copy is repaired, see `Git::repair`.
+/// The check reads the whole last snapshot, so it's only done on start or after a failure.
+pub fn open_repaired<R, P>(
+    reporter: R,
+    path: P,
+    location: &StoreLocation,
+) -> Result<Box<dyn WorldStore>, Error>
+where
+    R: StatusReporter + 'static,
+    P: AsRef<Path>,
+{
+    match location {
+        StoreLocation::Git { url } => Ok(Box::new(Git::new(reporter, path, url)?.repair(url)?)),
+        StoreLocation::Folder { .. } => open(reporter, path, location),
+    }
+}
+
+/// Moves the files of `path` to a folder next to it if they are not a local copy of a store,
+/// like a world that was there before the app was installed.
+/// Returns where they have been moved.
+pub fn set_aside_foreign_files(path: &Path) -> Result<Option<PathBuf>, Error> {
+    let is_local_copy = path.join(".git").exists() || path.join(local_dir::STATE_DIR).exists();
+    if is_local_copy || backup::world_files(path)?.is_empty() {
+        return Ok(None);
+    }
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let aside = backup::free_path(path.with_file_name(format!("{} previous", name)));
+    fs::rename(path, &aside)?;
+    fs::create_dir_all(path)?;
+    Ok(Some(aside))
+}
+
+impl<R: StatusReporter + 'static> WorldStore for Git<R> {
+    fn work_dir(&self) -> &PathBuf {
+        Git::work_dir(self)
+    }
+
+    fn commit_all(&self, message: &str) -> Result<(), Error> {
+        Git::commit_all(self, message)
+    }
+
+    fn commit_all_forced(&self, message: &str) -> Result<(), Error> {
+        Git::commit_all_forced(self, message)
+    }
+
+    fn pull(&self) -> Result<MergeStatus, Error> {
+        Git::pull(self)
+    }
+
+    fn push(&self) -> Result<(), Error> {
+        Git::push(self)
+    }
+
+    fn reset(&self) -> Result<(), Error> {
+        Git::reset_hard_to_origin(self)
+    }
+
+    fn has_unpushed_snapshots(&self) -> Result<bool, Error> {
+        Git::has_unpushed_snapshots(self)
+    }
+
+    fn fetch_lock(&self) -> Result<Option<String>, Error> {
+        Git::fetch_lock(self)
+    }
+
+    fn push_lock(&self, host: Option<&str>) -> Result<(), Error> {
+        Git::push_lock(self, host)
+    }
+
+    fn changed_files_from_origin(&self) -> Result<Vec<String>, Error> {
+        Git::changed_files_from_origin(self)
+    }
+
+    fn reclone(self: Box<Self>) -> Result<Box<dyn WorldStore>, Error> {
+        Ok(Box::new(Git::reclone(*self)?))
+    }
+}